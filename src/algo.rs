@@ -1,49 +1,204 @@
-use image::{Rgb, RgbImage};
-use imageproc::drawing::draw_polygon_mut;
-use imageproc::point::Point;
-use rand::distributions::{Distribution, Uniform};
+use crate::error_guide::ErrorGuide;
+use crate::fitness::{mse, optimal_color, pixel_weights, psnr, ErrorMap, FitnessMetric};
+use crate::genome::evolve_genome;
+use crate::optimizer::{
+    new_optimizer, CoolingSchedule, Crossover, DeStrategy, Optimizer, OptimizerKind, Selection,
+    StepSchedule,
+};
+use crate::pyramid::Level;
+use crate::refine::{prune_shapes, refine_shapes, render_shapes};
+use crate::saliency::Saliency;
+use crate::shape::{Constraints, MutationSteps, Shape, ShapeKind};
+use image::{GrayImage, RgbImage};
 use rand::prelude::StdRng;
-use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
 use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
-use svg::node::element::{Polygon, Rectangle};
+use std::time::{Duration, Instant, SystemTime};
+use svg::node::element::{Element, Rectangle};
+use svg::node::Text;
+use svg::Node;
 use svg::Document;
 
-#[derive(Clone)]
-pub struct Triangle {
-    pub vertices: [[i32; 2]; 3],
-    pub color: [u8; 3],
+/// How the shapes of the image are found.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EvolutionMode {
+    /// Evolve one shape at a time on top of those already placed.
+    Incremental,
+    /// Evolve the complete ordered list of shapes at once.
+    WholeImage,
+}
+
+impl EvolutionMode {
+    pub const ALL: [EvolutionMode; 2] = [EvolutionMode::Incremental, EvolutionMode::WholeImage];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EvolutionMode::Incremental => "Incremental",
+            EvolutionMode::WholeImage => "Whole Image",
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct AlgorithmParams {
+    pub evolution_mode: EvolutionMode,
     pub num_triangles: usize,
+    pub shape_kind: ShapeKind,
     pub image_size: u32,
     pub num_generations: usize,
     pub population_size: usize,
     pub num_selected: usize,
+    pub selection: Selection,
+    pub tournament_size: usize,
+    /// Number of the fittest candidates copied unchanged into the next generation.
+    pub elitism: usize,
+    pub crossover: Crossover,
     pub mutation_rate: f64,
+    pub mutation_steps: MutationSteps,
+    pub step_schedule: StepSchedule,
+    /// How much smaller mutation steps are for the last shape than for the
+    /// first, from 0 to 1, so that late shapes are fine-tuned.
+    pub step_shrink: f64,
+    pub fitness_metric: FitnessMetric,
+    pub optimizer: OptimizerKind,
+    /// Initial simulated annealing temperature, relative to the current error.
+    pub temperature: f64,
+    pub cooling_schedule: CoolingSchedule,
+    pub de_strategy: DeStrategy,
+    /// Differential evolution scale factor F applied to difference vectors.
+    pub differential_weight: f64,
+    /// Differential evolution probability CR of taking each gene from the mutant.
+    pub crossover_rate: f64,
+    /// Number of populations evolved side by side for every shape, each with its
+    /// own optimizer and seed. The best shape found on any of them is placed.
+    pub islands: usize,
+    /// Generations between migrations, in which the best candidates of every
+    /// island replace the worst of the next one; 0 disables migration.
+    pub migration_interval: usize,
+    /// Number of candidates that move to the next island at each migration.
+    pub migration_size: usize,
+    /// How strongly detailed regions of the reference are favored, from 0 to 1.
+    pub saliency: f64,
+    /// Compute each candidate's color from its geometry instead of evolving it.
+    pub optimal_color: bool,
+    /// Start the search for each shape where the canvas still differs most from
+    /// the reference, with candidates sized to the error around them.
+    pub error_guided: bool,
+    /// Force large shapes early in the run and progressively smaller ones later,
+    /// through area limits that shrink with every shape placed.
+    pub coarse_to_fine: bool,
+    /// With `coarse_to_fine`, measure the fitness of shapes that are still large
+    /// on a downscaled copy of the canvas and the reference.
+    pub pyramid: bool,
+    /// Number of refinement passes over all shapes once they have been placed.
+    pub refine_passes: usize,
+    /// Run a refinement pass after every this many shapes; 0 disables it.
+    pub refine_interval: usize,
+    /// Generations spent re-optimizing each shape during refinement.
+    pub refine_generations: usize,
+    /// Once done, remove shapes whose removal would raise the error by at most
+    /// this fraction of it, along with any that are hidden or do harm.
+    pub prune_threshold: Option<f64>,
+    /// Stop adding shapes once the RGB mean squared error is at or below this.
+    pub target_mse: Option<f64>,
+    /// Stop adding shapes once the PSNR in decibels is at or above this.
+    pub target_psnr: Option<f64>,
+    /// End the search for a shape after this many generations without improvement.
+    /// In whole-image mode, end the run after this many per shape. Must be at
+    /// least 1.
+    pub stagnation_generations: Option<usize>,
+    /// Wall-clock budget for the whole run, after which the shapes placed so far
+    /// are saved.
+    pub time_budget: Option<Duration>,
+    pub min_alpha: u8,
+    pub max_alpha: u8,
     pub degeneracy_threshold: Option<f64>,
+    /// Limits on the placement, size and shape of every shape.
+    pub constraints: Constraints,
+    /// Decimal places of the coordinates written to the SVG.
+    pub svg_precision: usize,
     pub seed: Option<u64>,
 }
 
 impl Default for AlgorithmParams {
     fn default() -> Self {
         Self {
+            evolution_mode: EvolutionMode::Incremental,
             num_triangles: 512,
+            shape_kind: ShapeKind::Triangle,
             image_size: 256,
             num_generations: 256,
             population_size: 128,
             num_selected: 64,
+            selection: Selection::Truncation,
+            tournament_size: 3,
+            elitism: 0,
+            crossover: Crossover::Uniform,
             mutation_rate: 0.1,
+            mutation_steps: MutationSteps::default(),
+            step_schedule: StepSchedule::Fixed,
+            step_shrink: 0.0,
+            fitness_metric: FitnessMetric::Mse,
+            optimizer: OptimizerKind::Genetic,
+            temperature: 0.001,
+            cooling_schedule: CoolingSchedule::Exponential,
+            de_strategy: DeStrategy::RandOne,
+            differential_weight: 0.5,
+            crossover_rate: 0.9,
+            islands: 1,
+            migration_interval: 16,
+            migration_size: 2,
+            saliency: 0.0,
+            optimal_color: false,
+            error_guided: false,
+            coarse_to_fine: false,
+            pyramid: false,
+            refine_passes: 0,
+            refine_interval: 0,
+            refine_generations: 32,
+            prune_threshold: None,
+            target_mse: None,
+            target_psnr: None,
+            stagnation_generations: None,
+            time_budget: None,
+            min_alpha: 32,
+            max_alpha: 255,
             degeneracy_threshold: None,
+            constraints: Constraints::default(),
+            svg_precision: 2,
             seed: None,
         }
     }
 }
 
+impl AlgorithmParams {
+    /// Constraints for a shape placed once `run_progress` of the run is done.
+    ///
+    /// With `coarse_to_fine`, the largest area allowed shrinks geometrically from
+    /// the whole image to four times an even share of it, and the smallest stays
+    /// a sixteenth of the largest. Limits set in `constraints` still apply; if
+    /// they conflict with the schedule, they win.
+    pub(crate) fn constraints_at(&self, run_progress: f64) -> Constraints {
+        if !self.coarse_to_fine {
+            return self.constraints;
+        }
+        let final_area = (4.0 / self.num_triangles.max(1) as f64).min(1.0);
+        let max_area = final_area.powf(run_progress.clamp(0.0, 1.0));
+        let min_area = max_area / 16.0;
+
+        let mut constraints = self.constraints;
+        let max_area = constraints.max_area.map_or(max_area, |max| max.min(max_area));
+        let min_area = constraints.min_area.map_or(min_area, |min| min.max(min_area));
+        if min_area > max_area {
+            return self.constraints;
+        }
+        constraints.max_area = Some(max_area);
+        constraints.min_area = Some(min_area);
+        constraints
+    }
+}
+
 #[derive(Clone)]
 pub struct Progress {
     pub triangle_index: usize,
@@ -52,7 +207,12 @@ pub struct Progress {
     pub is_complete: bool,
     pub current_fitness: f64,
     pub should_stop: bool,
-    pub current_generation: Vec<Triangle>,
+    /// Set while placed shapes are being refined; `triangle_index` is then the
+    /// shape being revisited.
+    pub is_refining: bool,
+    /// Number of shapes removed by pruning at the end of the run.
+    pub num_pruned: usize,
+    pub current_generation: Vec<Shape>,
 }
 
 impl Default for Progress {
@@ -64,19 +224,90 @@ impl Default for Progress {
             is_complete: false,
             current_fitness: f64::MIN,
             should_stop: false,
+            is_refining: false,
+            num_pruned: 0,
             current_generation: Vec::new(),
         }
     }
 }
 
+impl Progress {
+    /// `current_fitness` to two decimals, or "-" before anything has been
+    /// evaluated.
+    pub fn fitness_label(&self) -> String {
+        if self.current_fitness > f64::MIN {
+            format!("{:.2}", self.current_fitness)
+        } else {
+            "-".to_string()
+        }
+    }
+}
+
+/// Runs the algorithm and saves the result to `output_path`, returning the error
+/// if saving fails. Intermediate results are published through `current_canvas`
+/// and `current_svg` as shapes are placed, and setting `should_stop` in
+/// `progress` ends the run early.
+///
+/// `weight_map`, if given, must match the reference image in size. Brighter pixels
+/// count for more in the fitness function, so those regions get more detail.
 pub fn run_algorithm(
     params: AlgorithmParams,
     reference_image: RgbImage,
+    weight_map: Option<GrayImage>,
     output_path: String,
     progress: Arc<Mutex<Progress>>,
     current_canvas: Arc<Mutex<Option<RgbImage>>>,
     current_svg: Arc<Mutex<Option<Document>>>,
-) {
+) -> std::io::Result<()> {
+    let document = evolve(
+        &params,
+        &reference_image,
+        weight_map.as_ref(),
+        &progress,
+        &current_canvas,
+        &current_svg,
+    );
+
+    // Save final result
+    let saved = svg::save(&output_path, &document);
+
+    // Mark as complete
+    {
+        let mut p = progress.lock().unwrap();
+        p.is_running = false;
+        p.is_complete = true;
+        p.should_stop = false;
+    }
+    saved
+}
+
+/// Runs the algorithm to completion on the calling thread and returns the SVG.
+/// `reference_image` and `weight_map`, if given, must already be `image_size`
+/// pixels square.
+pub fn render(
+    params: AlgorithmParams,
+    reference_image: &RgbImage,
+    weight_map: Option<&GrayImage>,
+) -> Document {
+    evolve(
+        &params,
+        reference_image,
+        weight_map,
+        &Mutex::default(),
+        &Mutex::new(None),
+        &Mutex::new(None),
+    )
+}
+
+fn evolve(
+    params: &AlgorithmParams,
+    reference_image: &RgbImage,
+    weight_map: Option<&GrayImage>,
+    progress: &Mutex<Progress>,
+    current_canvas: &Mutex<Option<RgbImage>>,
+    current_svg: &Mutex<Option<Document>>,
+) -> Document {
+    let start_time = Instant::now();
     let seed = params.seed.unwrap_or_else(|| {
         SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -87,28 +318,89 @@ pub fn run_algorithm(
     let mut rng = StdRng::seed_from_u64(seed);
     let image_size = (params.image_size, params.image_size);
     let mut canvas_image = RgbImage::new(params.image_size, params.image_size);
-
-    let mut document = Document::new()
-        .set("width", params.image_size)
-        .set("height", params.image_size)
-        .set("viewBox", (0, 0, params.image_size, params.image_size))
-        .set("overflow", "hidden")
-        .add(
-            Rectangle::new()
-                .set("x", 0)
-                .set("y", 0)
-                .set("width", params.image_size)
-                .set("height", params.image_size)
-                .set("fill", "black"),
+    let saliency = (params.saliency > 0.0).then(|| Saliency::new(reference_image, params.saliency));
+    let mut weights = pixel_weights(weight_map, image_size);
+    if let Some(saliency) = &saliency {
+        let combined: Vec<f64> = weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| weight * saliency.weight(i))
+            .collect();
+        // At full strength, a reference that is flat wherever the weight map
+        // counts leaves no weight at all, so the saliency is ignored then.
+        if combined.iter().any(|&weight| weight > 0.0) {
+            weights = combined;
+        }
+    }
+    let mut error_map = ErrorMap::new(
+        &canvas_image,
+        reference_image,
+        params.fitness_metric,
+        &weights,
+    );
+    let mut document = new_document(params, Some(seed));
+    let mut shapes = Vec::new();
+    let should_stop = || {
+        progress.lock().unwrap().should_stop
+            || params.time_budget.is_some_and(|budget| start_time.elapsed() >= budget)
+    };
+    let refine = |shapes: &mut Vec<Shape>, rng: &mut StdRng| {
+        let canvas_image = refine_shapes(
+            shapes,
+            params,
+            reference_image,
+            &weights,
+            rng,
+            should_stop,
+            |k| {
+                let mut p = progress.lock().unwrap();
+                p.is_refining = true;
+                p.triangle_index = k;
+                p.generation_index = 0;
+            },
         );
+        progress.lock().unwrap().is_refining = false;
+        let error_map =
+            ErrorMap::new(&canvas_image, reference_image, params.fitness_metric, &weights);
+        let document = shapes_document(params, seed, shapes);
+        *current_canvas.lock().unwrap() = Some(canvas_image.clone());
+        *current_svg.lock().unwrap() = Some(document.clone());
+        (canvas_image, error_map, document)
+    };
+
+    let num_placements = match params.evolution_mode {
+        EvolutionMode::Incremental => params.num_triangles,
+        EvolutionMode::WholeImage => {
+            shapes = evolve_genome(
+                params,
+                reference_image,
+                &weights,
+                &mut rng,
+                should_stop,
+                |generation_index, genome, candidates| {
+                    let mut p = progress.lock().unwrap();
+                    p.triangle_index = genome.len().saturating_sub(1);
+                    p.generation_index = generation_index;
+                    p.current_generation = candidates;
+                },
+                |genome, canvas_image, fitness| {
+                    progress.lock().unwrap().current_fitness = fitness;
+                    *current_canvas.lock().unwrap() = Some(canvas_image.clone());
+                    *current_svg.lock().unwrap() = Some(shapes_document(params, seed, genome));
+                },
+            );
+            canvas_image = render_shapes(image_size, &shapes);
+            error_map = ErrorMap::new(&canvas_image, reference_image, params.fitness_metric, &weights);
+            document = shapes_document(params, seed, &shapes);
+            // All shapes have been evolved together, so none are left to place.
+            0
+        }
+    };
 
-    for triangle_index in 0..params.num_triangles {
+    for triangle_index in 0..num_placements {
         // Check if we should stop
-        {
-            let p = progress.lock().unwrap();
-            if p.should_stop {
-                break;
-            }
+        if should_stop() {
+            break;
         }
 
         {
@@ -117,17 +409,61 @@ pub fn run_algorithm(
             p.generation_index = 0;
         }
 
-        let mut population = generate_initial_population(params.population_size, image_size, &mut rng);
-        let mut best_triangle = None;
+        // The first island draws from the main generator, so a single island
+        // behaves like a plain population.
+        let mut island_rngs: Vec<StdRng> = (1..params.islands.max(1))
+            .map(|_| StdRng::seed_from_u64(rng.gen()))
+            .collect();
+        let mut rngs: Vec<&mut StdRng> =
+            std::iter::once(&mut rng).chain(island_rngs.iter_mut()).collect();
+        let run_progress = triangle_index as f64 / params.num_triangles as f64;
+        let shape_params = AlgorithmParams {
+            constraints: params.constraints_at(run_progress),
+            ..params.clone()
+        };
+        let level = shape_params
+            .constraints
+            .min_area
+            .filter(|_| params.coarse_to_fine && params.pyramid)
+            .and_then(|min_area| {
+                Level::for_min_area(
+                    min_area,
+                    &canvas_image,
+                    reference_image,
+                    params.fitness_metric,
+                    &weights,
+                )
+            });
+        let error_guide = params
+            .error_guided
+            .then(|| ErrorGuide::new(&canvas_image, reference_image, &weights));
+        let mut islands: Vec<Island> = rngs
+            .iter_mut()
+            .map(|rng| Island {
+                population: generate_initial_population(
+                    &shape_params,
+                    image_size,
+                    saliency.as_ref(),
+                    error_guide.as_ref(),
+                    rng,
+                ),
+                optimizer: new_optimizer(&shape_params, params.num_generations, run_progress),
+            })
+            .collect();
+        let mut best_shape = None;
         let mut best_fitness = f64::MIN;
+        let mut last_improvement = 0;
 
         for generation_index in 0..params.num_generations {
             // Check if we should stop
+            if should_stop() {
+                break;
+            }
+            if params
+                .stagnation_generations
+                .is_some_and(|n| generation_index - last_improvement >= n)
             {
-                let p = progress.lock().unwrap();
-                if p.should_stop {
-                    break;
-                }
+                break;
             }
 
             {
@@ -136,45 +472,96 @@ pub fn run_algorithm(
             }
 
             let degeneracy_threshold = params.degeneracy_threshold.unwrap_or(0.0);
-            let fitness_scores = evaluate_fitness_batch(
-                &population,
-                &canvas_image,
-                &reference_image,
-                degeneracy_threshold,
-            );
+            let mut fitness_scores: Vec<Vec<f64>> = islands
+                .par_iter_mut()
+                .map(|island| {
+                    let Some(level) = &level else {
+                        if params.optimal_color {
+                            assign_optimal_colors(
+                                &mut island.population,
+                                &canvas_image,
+                                reference_image,
+                                &weights,
+                            );
+                        }
+                        return evaluate_fitness_batch(
+                            &island.population,
+                            &canvas_image,
+                            reference_image,
+                            &error_map,
+                            degeneracy_threshold,
+                        );
+                    };
+                    let mut scaled: Vec<Shape> =
+                        island.population.iter().map(|shape| level.shape(shape)).collect();
+                    if params.optimal_color {
+                        assign_optimal_colors(
+                            &mut scaled,
+                            level.canvas_image(),
+                            level.reference_image(),
+                            level.weights(),
+                        );
+                        for (shape, scaled) in island.population.iter_mut().zip(&scaled) {
+                            *shape.color_mut() = scaled.color();
+                        }
+                    }
+                    evaluate_fitness_batch(
+                        &scaled,
+                        level.canvas_image(),
+                        level.reference_image(),
+                        level.error_map(),
+                        degeneracy_threshold,
+                    )
+                })
+                .collect();
+
+            for (island, scores) in islands.iter().zip(&fitness_scores) {
+                if let Some((shape, &fitness)) = island
+                    .population
+                    .iter()
+                    .zip(scores.iter())
+                    .max_by(|(_, f1), (_, f2)| f1.partial_cmp(f2).unwrap())
+                {
+                    if fitness > best_fitness {
+                        best_fitness = fitness;
+                        best_shape = Some(shape.clone());
+                        last_improvement = generation_index;
+
+                        let mut p = progress.lock().unwrap();
+                        p.current_fitness = fitness;
+                    }
+                }
+            }
 
-            if let Some((triangle, &fitness)) = population
-                .iter()
-                .zip(fitness_scores.iter())
-                .max_by(|(_, f1), (_, f2)| f1.partial_cmp(f2).unwrap())
+            if params.migration_interval > 0
+                && (generation_index + 1) % params.migration_interval == 0
             {
-                if fitness > best_fitness {
-                    best_fitness = fitness;
-                    best_triangle = Some(triangle.clone());
-
-                    let mut p = progress.lock().unwrap();
-                    p.current_fitness = fitness;
-                }
+                migrate(&mut islands, &mut fitness_scores, params.migration_size);
             }
 
-            population = select_population(&population, &fitness_scores, params.num_selected);
-            population = generate_new_population(
-                &population,
-                params.population_size,
-                image_size,
-                params.mutation_rate,
-                &mut rng,
-            );
+            islands
+                .par_iter_mut()
+                .zip(fitness_scores.par_iter())
+                .zip(rngs.par_iter_mut())
+                .for_each(|((island, scores), rng)| {
+                    let population = std::mem::take(&mut island.population);
+                    island.population = island.optimizer.next_generation(population, scores, rng);
+                });
 
             {
                 let mut p = progress.lock().unwrap();
-                p.current_generation = population.clone();
+                p.current_generation = islands
+                    .iter()
+                    .flat_map(|island| island.population.iter().cloned())
+                    .collect();
             }
         }
 
-        if let Some(triangle) = best_triangle {
-            draw_triangle_onto_canvas(&mut canvas_image, &triangle);
-            add_triangle_to_svg(&mut document, &triangle);
+        if let Some(shape) = best_shape {
+            draw_shape_onto_canvas(&mut canvas_image, &shape);
+            error_map.update(&shape, &canvas_image, reference_image);
+            add_shape_to_svg(&mut document, &shape, params.svg_precision);
+            shapes.push(shape);
 
             // Update shared state
             {
@@ -186,239 +573,406 @@ pub fn run_algorithm(
                 *svg_guard = Some(document.clone());
             }
         }
-    }
 
-    // Save final result
-    let _ = svg::save(&output_path, &document);
+        if params.target_mse.is_some() || params.target_psnr.is_some() {
+            let mse = mse(&canvas_image, reference_image);
+            if params.target_mse.is_some_and(|target| mse <= target)
+                || params.target_psnr.is_some_and(|target| psnr(mse) >= target)
+            {
+                break;
+            }
+        }
 
-    // Mark as complete
-    {
-        let mut p = progress.lock().unwrap();
-        p.is_running = false;
-        p.is_complete = true;
-        p.should_stop = false;
+        let placed = triangle_index + 1;
+        if params.refine_interval > 0
+            && placed % params.refine_interval == 0
+            && placed < params.num_triangles
+        {
+            (canvas_image, error_map, document) = refine(&mut shapes, &mut rng);
+        }
     }
-}
 
-fn generate_initial_population(
-    pop_size: usize,
-    image_size: (u32, u32),
-    rng: &mut impl Rng,
-) -> Vec<Triangle> {
-    let x_range = Uniform::from(0..image_size.0 as i32);
-    let y_range = Uniform::from(0..image_size.1 as i32);
-    let color_range = Uniform::from(0..=255u8);
-    let seeds: Vec<u64> = (0..pop_size).map(|_| rng.gen()).collect();
-
-    seeds
-        .into_par_iter()
-        .map(|seed| {
-            let mut thread_rng = StdRng::seed_from_u64(seed);
-            let v1 = [
-                x_range.sample(&mut thread_rng),
-                y_range.sample(&mut thread_rng),
-            ];
-            let v2 = [
-                x_range.sample(&mut thread_rng),
-                y_range.sample(&mut thread_rng),
-            ];
-            let v3 = [
-                x_range.sample(&mut thread_rng),
-                y_range.sample(&mut thread_rng),
-            ];
-            let vertices = [v1, v2, v3];
-            let color = [
-                color_range.sample(&mut thread_rng),
-                color_range.sample(&mut thread_rng),
-                color_range.sample(&mut thread_rng),
-            ];
-            Triangle { vertices, color }
-        })
-        .collect()
-}
-
-fn mutate(
-    triangle: &Triangle,
-    image_size: (u32, u32),
-    mutation_rate: f64,
-    rng: &mut impl Rng,
-) -> Triangle {
-    let mut new_triangle = triangle.clone();
-    if rng.gen::<f64>() < mutation_rate {
-        let x_range = (image_size.0 as f64 * 0.1) as i32;
-        let y_range = (image_size.1 as f64 * 0.1) as i32;
-
-        for i in 0..3 {
-            if rng.gen::<f64>() < 0.5 {
-                let x = new_triangle.vertices[i][0] + rng.gen_range(-x_range..=x_range);
-                let y = new_triangle.vertices[i][1] + rng.gen_range(-y_range..=y_range);
-                new_triangle.vertices[i][0] = x;
-                new_triangle.vertices[i][1] = y;
-            }
+    for _ in 0..params.refine_passes {
+        if should_stop() {
+            break;
         }
-        for i in 0..3 {
-            if rng.gen::<f64>() < 0.5 {
-                let color_component = new_triangle.color[i] as i32 + rng.gen_range(-10..=10);
-                new_triangle.color[i] = color_component.clamp(0, 255) as u8;
-            }
+        document = refine(&mut shapes, &mut rng).2;
+    }
+
+    if let Some(threshold) = params.prune_threshold.filter(|_| !should_stop()) {
+        let num_pruned = prune_shapes(&mut shapes, params, reference_image, &weights, threshold);
+        progress.lock().unwrap().num_pruned = num_pruned;
+        if num_pruned > 0 {
+            document = shapes_document(params, seed, &shapes);
+            *current_canvas.lock().unwrap() = Some(render_shapes(image_size, &shapes));
+            *current_svg.lock().unwrap() = Some(document.clone());
         }
     }
-    new_triangle
+
+    document
 }
 
-fn crossover(parent1: &Triangle, parent2: &Triangle, rng: &mut impl Rng) -> Triangle {
-    let mut child_vertices = [[0i32; 2]; 3];
-    for i in 0..3 {
-        child_vertices[i] = if rng.gen::<f64>() < 0.5 {
-            parent1.vertices[i]
-        } else {
-            parent2.vertices[i]
-        };
+/// Creates an empty black SVG document sized for `params`. The parameters of the
+/// run, including `seed` when known, are recorded in its `<metadata>` element.
+pub fn new_document(params: &AlgorithmParams, seed: Option<u64>) -> Document {
+    let mut metadata = format!(
+        "mode: {}\nshape: {}\ntriangles: {}\ngenerations: {}\npopulation: {}\nselected: {}\n\
+         mutation rate: {}\nfitness: {}\noptimizer: {}\nalpha: {}-{}\n",
+        params.evolution_mode.name(),
+        params.shape_kind.name(),
+        params.num_triangles,
+        params.num_generations,
+        params.population_size,
+        params.num_selected,
+        params.mutation_rate,
+        params.fitness_metric.name(),
+        params.optimizer.name(),
+        params.min_alpha,
+        params.max_alpha,
+    );
+    if params.optimizer == OptimizerKind::Genetic
+        && (params.selection != Selection::Truncation
+            || params.elitism > 0
+            || params.crossover != Crossover::Uniform)
+    {
+        metadata += &format!(
+            "selection: {}{}, elitism: {}, crossover: {}\n",
+            params.selection.name(),
+            match params.selection {
+                Selection::Tournament => format!(" of {}", params.tournament_size),
+                _ => String::new(),
+            },
+            params.elitism,
+            params.crossover.name()
+        );
     }
-    let mut child_color = [0u8; 3];
-    for i in 0..3 {
-        child_color[i] = if rng.gen::<f64>() < 0.5 {
-            parent1.color[i]
-        } else {
-            parent2.color[i]
-        };
+    if params.mutation_steps != MutationSteps::default()
+        || params.step_schedule != StepSchedule::Fixed
+        || params.step_shrink > 0.0
+    {
+        let steps = params.mutation_steps;
+        metadata += &format!(
+            "mutation steps: position {} angle {} color {} ({}, shrink {})\n",
+            steps.position,
+            steps.angle,
+            steps.color,
+            params.step_schedule.name(),
+            params.step_shrink
+        );
+    }
+    if params.optimizer == OptimizerKind::SimulatedAnnealing {
+        metadata += &format!(
+            "temperature: {} ({})\n",
+            params.temperature,
+            params.cooling_schedule.name()
+        );
+    }
+    if params.optimizer == OptimizerKind::DifferentialEvolution {
+        metadata += &format!(
+            "differential evolution: {} F={} CR={}\n",
+            params.de_strategy.name(),
+            params.differential_weight,
+            params.crossover_rate
+        );
+    }
+    if params.islands > 1 {
+        metadata += &format!(
+            "islands: {}, migrating {} every {} generations\n",
+            params.islands, params.migration_size, params.migration_interval
+        );
+    }
+    if params.optimal_color {
+        metadata += "optimal color: yes\n";
+    }
+    if params.error_guided {
+        metadata += "error guided: yes\n";
+    }
+    if params.coarse_to_fine {
+        metadata += &format!(
+            "coarse to fine: yes{}\n",
+            if params.pyramid { ", with pyramid" } else { "" }
+        );
     }
-    Triangle {
-        vertices: child_vertices,
-        color: child_color,
+    if params.saliency > 0.0 {
+        metadata += &format!("saliency: {}\n", params.saliency);
     }
+    if params.refine_passes > 0 || params.refine_interval > 0 {
+        metadata += &format!(
+            "refinement: {} passes, every {} shapes, {} generations\n",
+            params.refine_passes, params.refine_interval, params.refine_generations
+        );
+    }
+    if let Some(threshold) = params.prune_threshold {
+        metadata += &format!("prune threshold: {}\n", threshold);
+    }
+    if let Some(target) = params.target_mse {
+        metadata += &format!("target mse: {}\n", target);
+    }
+    if let Some(target) = params.target_psnr {
+        metadata += &format!("target psnr: {}\n", target);
+    }
+    if let Some(generations) = params.stagnation_generations {
+        metadata += &format!("stagnation generations: {}\n", generations);
+    }
+    if let Some(budget) = params.time_budget {
+        metadata += &format!("time budget: {}s\n", budget.as_secs_f64());
+    }
+    if let Some(threshold) = params.degeneracy_threshold {
+        metadata += &format!("degeneracy threshold: {}\n", threshold);
+    }
+    let constraints = &params.constraints;
+    for (name, value) in [
+        ("margin", constraints.margin),
+        ("min area", constraints.min_area),
+        ("max area", constraints.max_area),
+        ("max edge", constraints.max_edge),
+        ("max aspect ratio", constraints.max_aspect_ratio),
+    ] {
+        if let Some(value) = value {
+            metadata += &format!("{}: {}\n", name, value);
+        }
+    }
+    if let Some(seed) = seed {
+        metadata += &format!("seed: {}\n", seed);
+    }
+
+    Document::new()
+        .set("width", params.image_size)
+        .set("height", params.image_size)
+        .set("viewBox", (0, 0, params.image_size, params.image_size))
+        .set("overflow", "hidden")
+        .add({
+            let mut element = Element::new("metadata");
+            element.append(Text::new(metadata));
+            element
+        })
+        .add(
+            Rectangle::new()
+                .set("x", 0)
+                .set("y", 0)
+                .set("width", params.image_size)
+                .set("height", params.image_size)
+                .set("fill", "black"),
+        )
 }
 
-fn generate_new_population(
-    parents: &[Triangle],
-    population_size: usize,
+fn generate_initial_population(
+    params: &AlgorithmParams,
     image_size: (u32, u32),
-    mutation_rate: f64,
+    saliency: Option<&Saliency>,
+    error_guide: Option<&ErrorGuide>,
     rng: &mut impl Rng,
-) -> Vec<Triangle> {
-    let seeds: Vec<u64> = (0..population_size).map(|_| rng.gen()).collect();
+) -> Vec<Shape> {
+    let (shape_kind, constraints) = (params.shape_kind, &params.constraints);
+    let alpha_range = (params.min_alpha, params.max_alpha);
+    let seeds: Vec<u64> = (0..params.population_size).map(|_| rng.gen()).collect();
 
     seeds
         .into_par_iter()
         .map(|seed| {
             let mut thread_rng = StdRng::seed_from_u64(seed);
-            let parent1 = parents.choose(&mut thread_rng).unwrap();
-            let parent2 = parents.choose(&mut thread_rng).unwrap();
-            let child = crossover(parent1, parent2, &mut thread_rng);
-            mutate(&child, image_size, mutation_rate, &mut thread_rng)
+            let rng = &mut thread_rng;
+            match (error_guide, saliency) {
+                (Some(error_guide), _) => {
+                    error_guide.random_shape(shape_kind, image_size, alpha_range, constraints, rng)
+                }
+                (None, Some(saliency)) => {
+                    saliency.random_shape(shape_kind, image_size, alpha_range, constraints, rng)
+                }
+                (None, None) => Shape::random(shape_kind, image_size, alpha_range, constraints, rng),
+            }
         })
         .collect()
 }
 
-pub fn draw_triangle_onto_canvas(image: &mut RgbImage, triangle: &Triangle) {
-    let mut points = triangle
-        .vertices
-        .iter()
-        .map(|&v| Point::new(v[0], v[1]))
-        .collect::<Vec<_>>();
+pub fn draw_shape_onto_canvas(image: &mut RgbImage, shape: &Shape) {
+    let color = shape.color();
+    shape.rasterize(image.dimensions(), |x, y, coverage| {
+        let pixel = image.get_pixel_mut(x, y);
+        pixel.0 = blend_pixel(pixel.0, color, coverage);
+    });
+}
 
-    if points.len() > 2 && points[0] == points[points.len() - 1] {
-        points.pop();
+/// Blends `src` over `dst` where a shape covers the given fraction of the pixel.
+pub(crate) fn blend_pixel(dst: [u8; 3], src: [u8; 4], coverage: f64) -> [u8; 3] {
+    let alpha = (src[3] as f64 * coverage).round() as u32;
+    let mut out = [0u8; 3];
+    for (i, component) in out.iter_mut().enumerate() {
+        *component = ((src[i] as u32 * alpha + dst[i] as u32 * (255 - alpha) + 127) / 255) as u8;
     }
-    if points.len() < 3 {
-        return;
-    }
-
-    let color = Rgb([triangle.color[0], triangle.color[1], triangle.color[2]]);
-    draw_polygon_mut(image, &points, color);
+    out
 }
 
-fn compute_mse(image1: &RgbImage, image2: &RgbImage) -> f64 {
-    assert_eq!(image1.dimensions(), image2.dimensions());
-
-    let (width, height) = image1.dimensions();
-    let total_values = (width * height * 3) as f64;
-
-    let sum_squared_diff: f64 = image1
-        .pixels()
-        .zip(image2.pixels())
-        .map(|(p1, p2)| {
-            let rgb1 = p1.0;
-            let rgb2 = p2.0;
-            (rgb1[0] as f64 - rgb2[0] as f64).powi(2)
-                + (rgb1[1] as f64 - rgb2[1] as f64).powi(2)
-                + (rgb1[2] as f64 - rgb2[2] as f64).powi(2)
-        })
-        .sum();
+/// Smallest box containing both bounding boxes.
+pub(crate) fn union_box(
+    (min_a, max_a): ([f64; 2], [f64; 2]),
+    (min_b, max_b): ([f64; 2], [f64; 2]),
+) -> ([f64; 2], [f64; 2]) {
+    (
+        [min_a[0].min(min_b[0]), min_a[1].min(min_b[1])],
+        [max_a[0].max(max_b[0]), max_a[1].max(max_b[1])],
+    )
+}
 
-    sum_squared_diff / total_values
+/// Pixels from the returned minimum up to, excluding, the maximum that a shape
+/// with the given bounding box can touch on an image of `size`.
+pub(crate) fn pixel_box((min, max): ([f64; 2], [f64; 2]), size: (u32, u32)) -> ([u32; 2], [u32; 2]) {
+    let x0 = min[0].floor().clamp(0.0, size.0 as f64) as u32;
+    let y0 = min[1].floor().clamp(0.0, size.1 as f64) as u32;
+    let x1 = (max[0].ceil() + 1.0).clamp(0.0, size.0 as f64) as u32;
+    let y1 = (max[1].ceil() + 1.0).clamp(0.0, size.1 as f64) as u32;
+    ([x0, y0], [x1, y1])
 }
 
-fn is_degenerate(triangle: &Triangle, threshold: f64) -> bool {
-    let a = triangle.vertices[0];
-    let b = triangle.vertices[1];
-    let c = triangle.vertices[2];
+/// Draws `shapes` over `below`, or over black without it, within the pixels
+/// from `min` up to, excluding, `max`, and returns those whose color then
+/// differs from `canvas_image`.
+pub(crate) fn redrawn_pixels<'a>(
+    canvas_image: &RgbImage,
+    below: Option<&RgbImage>,
+    shapes: impl IntoIterator<Item = &'a Shape>,
+    (min, max): ([u32; 2], [u32; 2]),
+) -> Vec<([u32; 2], [u8; 3])> {
+    if min[0] >= max[0] || min[1] >= max[1] {
+        return Vec::new();
+    }
+    let region_width = max[0] - min[0];
+    let mut region = Vec::with_capacity((region_width * (max[1] - min[1])) as usize);
+    for y in min[1]..max[1] {
+        for x in min[0]..max[0] {
+            region.push(below.map_or([0; 3], |below| below.get_pixel(x, y).0));
+        }
+    }
+    for shape in shapes {
+        let color = shape.color();
+        shape.rasterize_within(min, max, |x, y, coverage| {
+            let i = ((y - min[1]) * region_width + x - min[0]) as usize;
+            region[i] = blend_pixel(region[i], color, coverage);
+        });
+    }
 
-    let ab = ((b[0] - a[0]).pow(2) + (b[1] - a[1]).pow(2)) as f64;
-    let bc = ((c[0] - b[0]).pow(2) + (c[1] - b[1]).pow(2)) as f64;
-    let ca = ((a[0] - c[0]).pow(2) + (a[1] - c[1]).pow(2)) as f64;
+    region
+        .iter()
+        .enumerate()
+        .map(|(i, &rgb)| {
+            (
+                [min[0] + i as u32 % region_width, min[1] + i as u32 / region_width],
+                rgb,
+            )
+        })
+        .filter(|&([x, y], rgb)| canvas_image.get_pixel(x, y).0 != rgb)
+        .collect()
+}
 
-    let angle_a = ((bc + ca - ab) / (2.0 * (bc * ca).sqrt()))
-        .acos()
-        .to_degrees();
-    let angle_b = ((ca + ab - bc) / (2.0 * (ca * ab).sqrt()))
-        .acos()
-        .to_degrees();
-    let angle_c = ((ab + bc - ca) / (2.0 * (ab * bc).sqrt()))
+/// Checks whether any interior angle of a polygonal shape is at or below
+/// `threshold` degrees. Curved shapes are never considered degenerate.
+pub(crate) fn is_degenerate(shape: &Shape, threshold: f64) -> bool {
+    let Some(points) = shape.polygon() else {
+        return false;
+    };
+
+    let n = points.len();
+    (0..n).any(|i| {
+        let a = points[(i + n - 1) % n];
+        let b = points[i];
+        let c = points[(i + 1) % n];
+
+        let ba = [a[0] - b[0], a[1] - b[1]];
+        let bc = [c[0] - b[0], c[1] - b[1]];
+        let angle = ((ba[0] * bc[0] + ba[1] * bc[1])
+            / ((ba[0].powi(2) + ba[1].powi(2)) * (bc[0].powi(2) + bc[1].powi(2))).sqrt())
         .acos()
         .to_degrees();
 
-    angle_a <= threshold || angle_b <= threshold || angle_c <= threshold
+        // A NaN angle means two vertices coincide.
+        angle.is_nan() || angle <= threshold
+    })
+}
+
+/// One of the populations searching for the next shape, see
+/// [`AlgorithmParams::islands`].
+struct Island {
+    population: Vec<Shape>,
+    optimizer: Box<dyn Optimizer>,
+}
+
+/// Copies the `count` fittest candidates of every island over the least fit of
+/// the next one, in a ring, together with their fitness.
+fn migrate(islands: &mut [Island], fitness_scores: &mut [Vec<f64>], count: usize) {
+    if islands.len() < 2 {
+        return;
+    }
+    let ranked: Vec<Vec<usize>> = fitness_scores
+        .iter()
+        .map(|scores| {
+            let mut indices: Vec<usize> = (0..scores.len()).collect();
+            indices.sort_by(|&a, &b| scores[b].partial_cmp(&scores[a]).unwrap());
+            indices
+        })
+        .collect();
+    let emigrants: Vec<Vec<(Shape, f64)>> = islands
+        .iter()
+        .zip(fitness_scores.iter())
+        .zip(&ranked)
+        .map(|((island, scores), ranked)| {
+            ranked
+                .iter()
+                .take(count)
+                .map(|&i| (island.population[i].clone(), scores[i]))
+                .collect()
+        })
+        .collect();
+
+    let n = islands.len();
+    for (target, ranked) in ranked.iter().enumerate() {
+        let source = &emigrants[(target + n - 1) % n];
+        for ((shape, fitness), &i) in source.iter().zip(ranked.iter().rev()) {
+            islands[target].population[i] = shape.clone();
+            fitness_scores[target][i] = *fitness;
+        }
+    }
 }
 
 fn evaluate_fitness_batch(
-    population: &[Triangle],
+    population: &[Shape],
     canvas_image: &RgbImage,
     reference_image: &RgbImage,
+    error_map: &ErrorMap,
     degeneracy_threshold: f64,
 ) -> Vec<f64> {
     population
         .par_iter()
-        .map(|triangle| {
-            if degeneracy_threshold > 0.0 && is_degenerate(triangle, degeneracy_threshold) {
+        .map(|shape| {
+            if degeneracy_threshold > 0.0 && is_degenerate(shape, degeneracy_threshold) {
                 f64::MIN
             } else {
-                let mut working_image = canvas_image.clone();
-                draw_triangle_onto_canvas(&mut working_image, triangle);
-                -compute_mse(&working_image, reference_image)
+                -error_map.error_with(shape, canvas_image, reference_image)
             }
         })
         .collect()
 }
 
-fn select_population(
-    population: &[Triangle],
-    fitness_scores: &[f64],
-    num_selected: usize,
-) -> Vec<Triangle> {
-    let mut combined: Vec<_> = population.iter().zip(fitness_scores.iter()).collect();
-    combined.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
-    combined
-        .iter()
-        .take(num_selected)
-        .map(|(triangle, _)| (*triangle).clone())
-        .collect()
+fn assign_optimal_colors(
+    population: &mut [Shape],
+    canvas_image: &RgbImage,
+    reference_image: &RgbImage,
+    weights: &[f64],
+) {
+    population.par_iter_mut().for_each(|shape| {
+        let color = optimal_color(shape, canvas_image, reference_image, weights);
+        shape.color_mut()[..3].copy_from_slice(&color);
+    });
 }
 
-fn add_triangle_to_svg(document: &mut Document, triangle: &Triangle) {
-    let points = triangle
-        .vertices
+/// Creates a document for `params` holding `shapes` in drawing order.
+fn shapes_document(params: &AlgorithmParams, seed: u64, shapes: &[Shape]) -> Document {
+    shapes
         .iter()
-        .map(|v| format!("{},{}", v[0], v[1]))
-        .collect::<Vec<_>>()
-        .join(" ");
-
-    let color = format!(
-        "rgb({},{},{})",
-        triangle.color[0], triangle.color[1], triangle.color[2]
-    );
-
-    let polygon = Polygon::new().set("points", points).set("fill", color);
+        .fold(new_document(params, Some(seed)), |document, shape| {
+            document.add(shape.svg_node(params.svg_precision))
+        })
+}
 
-    *document = document.clone().add(polygon);
-}
\ No newline at end of file
+fn add_shape_to_svg(document: &mut Document, shape: &Shape, precision: usize) {
+    *document = document.clone().add(shape.svg_node(precision));
+}
@@ -0,0 +1,400 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use triklops::algo::{run_algorithm, AlgorithmParams, EvolutionMode, Progress};
+use triklops::fitness::FitnessMetric;
+use triklops::optimizer::{
+    CoolingSchedule, Crossover, DeStrategy, OptimizerKind, Selection, StepSchedule,
+};
+use triklops::shape::ShapeKind;
+
+const USAGE: &str = "\
+Usage: triklops run <INPUT> [OPTIONS]
+
+Options:
+  -o, --output <PATH>               Output SVG path [default: INPUT with .svg extension]
+      --weights <PATH>              Grayscale image; brighter regions get more detail
+      --mode <MODE>                 incremental, or whole-image to evolve all shapes at once
+      --shape <KIND>                triangle, quad, rectangle, ellipse, circle or mixed
+      --triangles <N>               Number of shapes to place
+      --image-size <PX>             Working resolution the reference is resized to
+      --generations <N>             Generations per shape
+      --population <N>              Population size
+      --selected <N>                Individuals kept after each generation
+      --selection <KIND>            truncation, tournament, roulette or rank
+      --tournament-size <N>         Candidates per tournament
+      --elitism <N>                 Fittest candidates kept unchanged each generation
+      --crossover <KIND>            uniform, arithmetic or blend
+      --mutation-rate <RATE>        Probability that a child is mutated
+      --position-step <FRACTION>    Largest vertex move as a fraction of the image size
+      --angle-step <DEG>            Largest rotation of rectangles and ellipses
+      --color-step <N>              Largest change of a color channel or alpha
+      --step-schedule <SCHEDULE>    fixed, one-fifth or decay
+      --step-shrink <0-1>           Shrink steps toward the end of the run
+      --fitness <METRIC>            mse, de76, de2000, ssim or ms-ssim
+      --optimizer <KIND>            ga, hill-climbing, annealing, cma-es or de
+      --temperature <T>             Initial annealing temperature, relative to the error
+      --cooling <SCHEDULE>          Annealing schedule: linear or exponential
+      --de-strategy <STRATEGY>      Differential evolution: rand or best
+      --differential-weight <F>     Differential evolution scale factor
+      --crossover-rate <CR>         Differential evolution crossover probability
+      --islands <N>                 Populations evolved side by side for each shape
+      --migration-interval <N>      Generations between migrations, 0 for none
+      --migration-size <N>          Candidates that move to the next island
+      --optimal-color               Compute shape colors instead of evolving them
+      --error-guided                Start each shape where the error is largest
+      --coarse-to-fine              Place large shapes first and smaller ones later
+      --pyramid                     Score large shapes on a downscaled image
+      --saliency <0-1>              Favor detailed regions of the reference
+      --refine-passes <N>           Refinement passes over all shapes at the end
+      --refine-interval <N>         Also refine after every N shapes
+      --refine-generations <N>      Generations per shape when refining
+      --prune <THRESHOLD>           Drop shapes worth less than this fraction of the error
+      --target-mse <MSE>            Stop adding shapes once the RGB MSE reaches this
+      --target-psnr <DB>            Stop adding shapes once the PSNR reaches this
+      --stagnation <N>              End a shape's search after N idle generations
+      --time-budget <SECONDS>       Save what has been placed after this long
+      --min-alpha <0-255>           Lowest shape opacity
+      --max-alpha <0-255>           Highest shape opacity
+      --degeneracy-threshold <DEG>  Reject polygons with an angle at or below DEG
+      --margin <FRACTION>           Keep vertices within this margin of the canvas
+      --min-area <FRACTION>         Smallest shape area, as a fraction of the image
+      --max-area <FRACTION>         Largest shape area, as a fraction of the image
+      --max-edge <FRACTION>         Longest edge, as a fraction of the image size
+      --max-aspect <RATIO>          Largest ratio of a shape's length to its width
+      --svg-precision <N>           Decimal places of SVG coordinates
+      --seed <N>                    Random seed
+  -h, --help                        Print this help";
+
+struct Options {
+    params: AlgorithmParams,
+    input_path: String,
+    output_path: Option<String>,
+    weights_path: Option<String>,
+}
+
+/// Runs Tri-Klops without a window. `args` excludes the program name.
+/// Returns the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    let options = match parse_args(args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return 0;
+        }
+        Err(message) => {
+            eprintln!("error: {message}\n\n{USAGE}");
+            return 2;
+        }
+    };
+
+    let params = options.params;
+    let reference_image = match image::open(&options.input_path) {
+        Ok(img) => img
+            .resize_exact(
+                params.image_size,
+                params.image_size,
+                image::imageops::FilterType::Lanczos3,
+            )
+            .to_rgb8(),
+        Err(err) => {
+            eprintln!("error: could not open {}: {err}", options.input_path);
+            return 1;
+        }
+    };
+    let weight_map = match options.weights_path.as_deref().map(image::open) {
+        None => None,
+        Some(Ok(img)) => Some(
+            img.resize_exact(
+                params.image_size,
+                params.image_size,
+                image::imageops::FilterType::Lanczos3,
+            )
+            .to_luma8(),
+        ),
+        Some(Err(err)) => {
+            eprintln!(
+                "error: could not open {}: {err}",
+                options.weights_path.unwrap_or_default()
+            );
+            return 1;
+        }
+    };
+    let output_path = options.output_path.unwrap_or_else(|| {
+        Path::new(&options.input_path)
+            .with_extension("svg")
+            .display()
+            .to_string()
+    });
+
+    let progress = Arc::new(Mutex::new(Progress {
+        is_running: true,
+        ..Progress::default()
+    }));
+    let num_triangles = params.num_triangles;
+    let prune = params.prune_threshold.is_some();
+
+    let worker = {
+        let progress = Arc::clone(&progress);
+        let output_path = output_path.clone();
+        thread::spawn(move || {
+            run_algorithm(
+                params,
+                reference_image,
+                weight_map,
+                output_path,
+                progress,
+                Arc::new(Mutex::new(None)),
+                Arc::new(Mutex::new(None)),
+            )
+        })
+    };
+
+    let mut last_reported = None;
+    // Polled until the thread ends rather than until the run is complete, which
+    // it never is if the thread panics.
+    while !worker.is_finished() {
+        let (triangle_index, fitness, is_refining) = {
+            let p = progress.lock().unwrap();
+            (p.triangle_index, p.fitness_label(), p.is_refining)
+        };
+        if last_reported != Some((triangle_index, is_refining)) {
+            if is_refining {
+                eprintln!("Refining: {}/{}", triangle_index + 1, num_triangles);
+            } else {
+                eprintln!("Triangle: {}/{}, Fitness: {}", triangle_index + 1, num_triangles, fitness);
+            }
+            last_reported = Some((triangle_index, is_refining));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    match worker.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+            eprintln!("error: failed to save {output_path}: {err}");
+            return 1;
+        }
+        Err(_) => {
+            eprintln!("error: algorithm thread panicked");
+            return 1;
+        }
+    }
+    if prune {
+        eprintln!("Pruned {} shapes", progress.lock().unwrap().num_pruned);
+    }
+    eprintln!("Saved {output_path}");
+    0
+}
+
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut args = args.iter();
+    match args.next().map(String::as_str) {
+        Some("run") => {}
+        Some("-h" | "--help") | None => return Ok(None),
+        Some(other) => return Err(format!("unknown command '{other}'")),
+    }
+
+    let mut params = AlgorithmParams::default();
+    let mut input_path = None;
+    let mut output_path = None;
+    let mut weights_path = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for '{arg}'"))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output_path = Some(value()?.clone()),
+            "--weights" => weights_path = Some(value()?.clone()),
+            "--mode" => params.evolution_mode = parse_mode(value()?)?,
+            "--shape" => params.shape_kind = parse_shape(value()?)?,
+            "--triangles" => params.num_triangles = parse_value(arg, value()?)?,
+            "--image-size" => params.image_size = parse_value(arg, value()?)?,
+            "--generations" => params.num_generations = parse_value(arg, value()?)?,
+            "--population" => params.population_size = parse_value(arg, value()?)?,
+            "--selected" => params.num_selected = parse_value(arg, value()?)?,
+            "--selection" => params.selection = parse_selection(value()?)?,
+            "--tournament-size" => params.tournament_size = parse_value(arg, value()?)?,
+            "--elitism" => params.elitism = parse_value(arg, value()?)?,
+            "--crossover" => params.crossover = parse_crossover(value()?)?,
+            "--mutation-rate" => params.mutation_rate = parse_value(arg, value()?)?,
+            "--position-step" => params.mutation_steps.position = parse_value(arg, value()?)?,
+            "--angle-step" => params.mutation_steps.angle = parse_value(arg, value()?)?,
+            "--color-step" => params.mutation_steps.color = parse_value(arg, value()?)?,
+            "--step-schedule" => params.step_schedule = parse_step_schedule(value()?)?,
+            "--step-shrink" => params.step_shrink = parse_value(arg, value()?)?,
+            "--fitness" => params.fitness_metric = parse_fitness(value()?)?,
+            "--optimizer" => params.optimizer = parse_optimizer(value()?)?,
+            "--temperature" => params.temperature = parse_value(arg, value()?)?,
+            "--cooling" => params.cooling_schedule = parse_cooling(value()?)?,
+            "--de-strategy" => params.de_strategy = parse_de_strategy(value()?)?,
+            "--differential-weight" => {
+                params.differential_weight = parse_value(arg, value()?)?
+            }
+            "--crossover-rate" => params.crossover_rate = parse_value(arg, value()?)?,
+            "--islands" => params.islands = parse_value(arg, value()?)?,
+            "--migration-interval" => params.migration_interval = parse_value(arg, value()?)?,
+            "--migration-size" => params.migration_size = parse_value(arg, value()?)?,
+            "--optimal-color" => params.optimal_color = true,
+            "--error-guided" => params.error_guided = true,
+            "--coarse-to-fine" => params.coarse_to_fine = true,
+            "--pyramid" => params.pyramid = true,
+            "--saliency" => params.saliency = parse_value(arg, value()?)?,
+            "--refine-passes" => params.refine_passes = parse_value(arg, value()?)?,
+            "--refine-interval" => params.refine_interval = parse_value(arg, value()?)?,
+            "--refine-generations" => params.refine_generations = parse_value(arg, value()?)?,
+            "--prune" => params.prune_threshold = Some(parse_value(arg, value()?)?),
+            "--target-mse" => params.target_mse = Some(parse_value(arg, value()?)?),
+            "--target-psnr" => params.target_psnr = Some(parse_value(arg, value()?)?),
+            "--stagnation" => params.stagnation_generations = Some(parse_value(arg, value()?)?),
+            "--time-budget" => {
+                let seconds: f64 = parse_value(arg, value()?)?;
+                if !(seconds >= 0.0 && seconds.is_finite()) {
+                    return Err(format!("invalid value '{seconds}' for '{arg}'"));
+                }
+                params.time_budget = Some(Duration::from_secs_f64(seconds));
+            }
+            "--min-alpha" => params.min_alpha = parse_value(arg, value()?)?,
+            "--max-alpha" => params.max_alpha = parse_value(arg, value()?)?,
+            "--degeneracy-threshold" => {
+                params.degeneracy_threshold = Some(parse_value(arg, value()?)?)
+            }
+            "--margin" => params.constraints.margin = Some(parse_value(arg, value()?)?),
+            "--min-area" => params.constraints.min_area = Some(parse_value(arg, value()?)?),
+            "--max-area" => params.constraints.max_area = Some(parse_value(arg, value()?)?),
+            "--max-edge" => params.constraints.max_edge = Some(parse_value(arg, value()?)?),
+            "--max-aspect" => {
+                params.constraints.max_aspect_ratio = Some(parse_value(arg, value()?)?)
+            }
+            "--svg-precision" => params.svg_precision = parse_value(arg, value()?)?,
+            "--seed" => params.seed = Some(parse_value(arg, value()?)?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{arg}'")),
+            _ if input_path.is_none() => input_path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument '{arg}'")),
+        }
+    }
+
+    for (name, value) in [
+        ("--image-size", params.image_size as usize),
+        ("--generations", params.num_generations),
+        ("--population", params.population_size),
+        ("--selected", params.num_selected),
+        ("--stagnation", params.stagnation_generations.unwrap_or(1)),
+    ] {
+        if value == 0 {
+            return Err(format!("{name} must be at least 1"));
+        }
+    }
+    if params.min_alpha > params.max_alpha {
+        return Err("--min-alpha must not exceed --max-alpha".to_string());
+    }
+    if params.pyramid && !params.coarse_to_fine {
+        return Err("--pyramid requires --coarse-to-fine".to_string());
+    }
+    let constraints = &params.constraints;
+    if constraints.margin.is_some_and(|margin| margin < 0.0) {
+        return Err("--margin must not be negative".to_string());
+    }
+    if let (Some(min), Some(max)) = (constraints.min_area, constraints.max_area) {
+        if min > max {
+            return Err("--min-area must not exceed --max-area".to_string());
+        }
+    }
+    if constraints.max_aspect_ratio.is_some_and(|ratio| ratio < 1.0) {
+        return Err("--max-aspect must be at least 1".to_string());
+    }
+
+    let input_path = input_path.ok_or("missing INPUT path")?;
+    Ok(Some(Options {
+        params,
+        input_path,
+        output_path,
+        weights_path,
+    }))
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{value}' for '{name}'"))
+}
+
+fn parse_shape(value: &str) -> Result<ShapeKind, String> {
+    ShapeKind::ALL
+        .into_iter()
+        .find(|kind| kind.name().eq_ignore_ascii_case(value))
+        .ok_or_else(|| format!("unknown shape '{value}'"))
+}
+
+fn parse_mode(value: &str) -> Result<EvolutionMode, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "incremental" => Ok(EvolutionMode::Incremental),
+        "whole-image" => Ok(EvolutionMode::WholeImage),
+        _ => Err(format!("unknown mode '{value}'")),
+    }
+}
+
+fn parse_fitness(value: &str) -> Result<FitnessMetric, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "mse" => Ok(FitnessMetric::Mse),
+        "de76" => Ok(FitnessMetric::DeltaE76),
+        "de2000" => Ok(FitnessMetric::DeltaE2000),
+        "ssim" => Ok(FitnessMetric::Ssim),
+        "ms-ssim" => Ok(FitnessMetric::MsSsim),
+        _ => Err(format!("unknown fitness metric '{value}'")),
+    }
+}
+
+fn parse_optimizer(value: &str) -> Result<OptimizerKind, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "ga" => Ok(OptimizerKind::Genetic),
+        "hill-climbing" => Ok(OptimizerKind::HillClimbing),
+        "annealing" => Ok(OptimizerKind::SimulatedAnnealing),
+        "cma-es" => Ok(OptimizerKind::CmaEs),
+        "de" => Ok(OptimizerKind::DifferentialEvolution),
+        _ => Err(format!("unknown optimizer '{value}'")),
+    }
+}
+
+fn parse_cooling(value: &str) -> Result<CoolingSchedule, String> {
+    CoolingSchedule::ALL
+        .into_iter()
+        .find(|schedule| schedule.name().eq_ignore_ascii_case(value))
+        .ok_or_else(|| format!("unknown cooling schedule '{value}'"))
+}
+
+fn parse_de_strategy(value: &str) -> Result<DeStrategy, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "rand" => Ok(DeStrategy::RandOne),
+        "best" => Ok(DeStrategy::BestOne),
+        _ => Err(format!("unknown differential evolution strategy '{value}'")),
+    }
+}
+
+fn parse_step_schedule(value: &str) -> Result<StepSchedule, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "fixed" => Ok(StepSchedule::Fixed),
+        "one-fifth" => Ok(StepSchedule::OneFifthRule),
+        "decay" => Ok(StepSchedule::Decay),
+        _ => Err(format!("unknown step schedule '{value}'")),
+    }
+}
+
+fn parse_selection(value: &str) -> Result<Selection, String> {
+    Selection::ALL
+        .into_iter()
+        .find(|selection| selection.name().eq_ignore_ascii_case(value))
+        .ok_or_else(|| format!("unknown selection '{value}'"))
+}
+
+fn parse_crossover(value: &str) -> Result<Crossover, String> {
+    Crossover::ALL
+        .into_iter()
+        .find(|crossover| crossover.name().eq_ignore_ascii_case(value))
+        .ok_or_else(|| format!("unknown crossover '{value}'"))
+}
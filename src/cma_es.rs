@@ -0,0 +1,350 @@
+use crate::optimizer::Optimizer;
+use crate::shape::{Constraints, Shape};
+use rand::prelude::StdRng;
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// How often a sample breaking the constraints is redrawn.
+const MAX_RESAMPLES: usize = 8;
+
+/// Covariance matrix adaptation evolution strategy over the genes of a shape,
+/// after Hansen's "The CMA Evolution Strategy: A Tutorial".
+///
+/// The search starts from the fittest shape of the initial population and keeps
+/// its kind, ignoring candidates of other kinds. Candidates are evaluated after
+/// clamping, color rounding and, with optimal colors, recoloring, so the update
+/// re-encodes them rather than trusting the samples. Samples breaking the
+/// shape constraints are redrawn a few times and then replaced by the starting
+/// shape.
+pub(crate) struct CmaEs {
+    scale: f64,
+    image_size: (u32, u32),
+    alpha_range: (u8, u8),
+    constraints: Constraints,
+    initial_sigma: f64,
+    lambda: usize,
+    mu: usize,
+    state: Option<State>,
+}
+
+struct State {
+    template: Shape,
+    weights: Vec<f64>,
+    mu_eff: f64,
+    c_sigma: f64,
+    d_sigma: f64,
+    c_c: f64,
+    c_1: f64,
+    c_mu: f64,
+    chi_n: f64,
+    mean: Vec<f64>,
+    sigma: f64,
+    covariance: Vec<Vec<f64>>,
+    p_sigma: Vec<f64>,
+    p_c: Vec<f64>,
+    /// Eigenvectors of the covariance as columns, and the square roots of the
+    /// matching eigenvalues.
+    basis: Vec<Vec<f64>>,
+    deviations: Vec<f64>,
+    generation_index: usize,
+}
+
+impl CmaEs {
+    /// `lambda` candidates are sampled each generation and the best `mu` of them
+    /// move the distribution. `initial_sigma` is the starting step size in genes,
+    /// which are fractions of the image size for positions.
+    pub fn new(
+        image_size: (u32, u32),
+        alpha_range: (u8, u8),
+        constraints: Constraints,
+        initial_sigma: f64,
+        lambda: usize,
+        mu: usize,
+    ) -> Self {
+        let lambda = lambda.max(2);
+        Self {
+            scale: image_size.0.max(image_size.1) as f64,
+            image_size,
+            alpha_range,
+            constraints,
+            initial_sigma,
+            lambda,
+            mu: mu.clamp(1, lambda),
+            state: None,
+        }
+    }
+}
+
+impl Optimizer for CmaEs {
+    fn next_generation(
+        &mut self,
+        candidates: Vec<Shape>,
+        fitness_scores: &[f64],
+        rng: &mut StdRng,
+    ) -> Vec<Shape> {
+        let mut ranked: Vec<_> = candidates.iter().zip(fitness_scores).collect();
+        ranked.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
+
+        match &mut self.state {
+            None => {
+                let Some((best, _)) = ranked.first() else {
+                    return Vec::new();
+                };
+                self.state = Some(State::new(best, self.scale, self.initial_sigma, self.mu));
+            }
+            Some(state) => {
+                // Candidates of other kinds have genes of a different length,
+                // so they cannot move the search.
+                let template_kind = std::mem::discriminant(&state.template);
+                let genes: Vec<Vec<f64>> = ranked
+                    .iter()
+                    .filter(|(shape, _)| std::mem::discriminant(*shape) == template_kind)
+                    .take(self.mu)
+                    .map(|(shape, _)| shape.genes(self.scale))
+                    .collect();
+                if !genes.is_empty() {
+                    state.update(&genes);
+                }
+            }
+        }
+
+        let state = self.state.as_ref().unwrap();
+        (0..self.lambda)
+            .map(|_| {
+                for _ in 0..MAX_RESAMPLES {
+                    let genes = state.sample(rng);
+                    let mut shape = state.template.with_genes(&genes, self.scale, self.alpha_range);
+                    self.constraints.clamp(&mut shape, self.image_size);
+                    if self.constraints.allows(&shape, self.image_size) {
+                        return shape;
+                    }
+                }
+                state.template.clone()
+            })
+            .collect()
+    }
+}
+
+impl State {
+    fn new(start: &Shape, scale: f64, sigma: f64, mu: usize) -> Self {
+        let mean = start.genes(scale);
+        let n = mean.len() as f64;
+
+        let mut weights: Vec<f64> = (1..=mu)
+            .map(|i| (mu as f64 + 0.5).ln() - (i as f64).ln())
+            .collect();
+        let sum: f64 = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= sum);
+        let mu_eff = 1.0 / weights.iter().map(|w| w * w).sum::<f64>();
+
+        let c_sigma = (mu_eff + 2.0) / (n + mu_eff + 5.0);
+        let c_1 = 2.0 / ((n + 1.3).powi(2) + mu_eff);
+        Self {
+            template: start.clone(),
+            weights,
+            mu_eff,
+            c_sigma,
+            d_sigma: 1.0 + 2.0 * (((mu_eff - 1.0) / (n + 1.0)).sqrt() - 1.0).max(0.0) + c_sigma,
+            c_c: (4.0 + mu_eff / n) / (n + 4.0 + 2.0 * mu_eff / n),
+            c_1,
+            c_mu: (1.0 - c_1)
+                .min(2.0 * (mu_eff - 2.0 + 1.0 / mu_eff) / ((n + 2.0).powi(2) + mu_eff)),
+            chi_n: n.sqrt() * (1.0 - 1.0 / (4.0 * n) + 1.0 / (21.0 * n * n)),
+            sigma,
+            covariance: identity(mean.len()),
+            p_sigma: vec![0.0; mean.len()],
+            p_c: vec![0.0; mean.len()],
+            basis: identity(mean.len()),
+            deviations: vec![1.0; mean.len()],
+            generation_index: 0,
+            mean,
+        }
+    }
+
+    /// Draws `mean + sigma * B * D * z` with `z` standard normal.
+    fn sample(&self, rng: &mut StdRng) -> Vec<f64> {
+        let z: Vec<f64> = self.deviations.iter().map(|d| d * standard_normal(rng)).collect();
+        let y = multiply(&self.basis, &z);
+        self.mean
+            .iter()
+            .zip(y)
+            .map(|(m, y)| m + self.sigma * y)
+            .collect()
+    }
+
+    /// Moves the distribution toward `selected`, the genes of the fittest
+    /// candidates in order of decreasing fitness.
+    fn update(&mut self, selected: &[Vec<f64>]) {
+        let n = self.mean.len();
+        let steps: Vec<Vec<f64>> = selected
+            .iter()
+            .map(|x| x.iter().zip(&self.mean).map(|(x, m)| (x - m) / self.sigma).collect())
+            .collect();
+        let weights = &self.weights[..steps.len()];
+        let mut step = vec![0.0; n];
+        for (w, y) in weights.iter().zip(&steps) {
+            for (s, y) in step.iter_mut().zip(y) {
+                *s += w * y;
+            }
+        }
+        for (m, s) in self.mean.iter_mut().zip(&step) {
+            *m += self.sigma * s;
+        }
+
+        // C^(-1/2) * step = B * D^(-1) * B^T * step
+        let rotated = multiply_transposed(&self.basis, &step);
+        let whitened: Vec<f64> = rotated
+            .iter()
+            .zip(&self.deviations)
+            .map(|(r, d)| r / d)
+            .collect();
+        let whitened = multiply(&self.basis, &whitened);
+
+        let c_sigma = self.c_sigma;
+        let factor = (c_sigma * (2.0 - c_sigma) * self.mu_eff).sqrt();
+        for (p, w) in self.p_sigma.iter_mut().zip(&whitened) {
+            *p = (1.0 - c_sigma) * *p + factor * w;
+        }
+        let p_sigma_norm = self.p_sigma.iter().map(|p| p * p).sum::<f64>().sqrt();
+
+        self.generation_index += 1;
+        let h_sigma = p_sigma_norm
+            / (1.0 - (1.0 - c_sigma).powi(2 * self.generation_index as i32)).sqrt()
+            < (1.4 + 2.0 / (n as f64 + 1.0)) * self.chi_n;
+        let h_sigma = if h_sigma { 1.0 } else { 0.0 };
+
+        let c_c = self.c_c;
+        let factor = h_sigma * (c_c * (2.0 - c_c) * self.mu_eff).sqrt();
+        for (p, s) in self.p_c.iter_mut().zip(&step) {
+            *p = (1.0 - c_c) * *p + factor * s;
+        }
+
+        let decay = 1.0 - self.c_1 - self.c_mu;
+        let correction = (1.0 - h_sigma) * c_c * (2.0 - c_c);
+        for i in 0..n {
+            for j in 0..n {
+                let rank_mu: f64 = weights
+                    .iter()
+                    .zip(&steps)
+                    .map(|(w, y)| w * y[i] * y[j])
+                    .sum();
+                let c = &mut self.covariance[i][j];
+                *c = decay * *c
+                    + self.c_1 * (self.p_c[i] * self.p_c[j] + correction * *c)
+                    + self.c_mu * rank_mu;
+            }
+        }
+
+        self.sigma *= ((c_sigma / self.d_sigma) * (p_sigma_norm / self.chi_n - 1.0)).exp();
+
+        let (eigenvalues, eigenvectors) = symmetric_eigen(&self.covariance);
+        self.deviations = eigenvalues.iter().map(|e| e.max(1e-20).sqrt()).collect();
+        self.basis = eigenvectors;
+    }
+}
+
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    // Box-Muller transform
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+fn identity(n: usize) -> Vec<Vec<f64>> {
+    (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect()
+}
+
+fn multiply(matrix: &[Vec<f64>], vector: &[f64]) -> Vec<f64> {
+    matrix
+        .iter()
+        .map(|row| row.iter().zip(vector).map(|(a, b)| a * b).sum())
+        .collect()
+}
+
+fn multiply_transposed(matrix: &[Vec<f64>], vector: &[f64]) -> Vec<f64> {
+    (0..vector.len())
+        .map(|j| matrix.iter().zip(vector).map(|(row, v)| row[j] * v).sum())
+        .collect()
+}
+
+/// Eigendecomposition of a small symmetric matrix by cyclic Jacobi rotations.
+/// Returns the eigenvalues and a matrix with the matching eigenvectors as columns.
+fn symmetric_eigen(matrix: &[Vec<f64>]) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = matrix.len();
+    let mut a = matrix.to_vec();
+    let mut v = identity(n);
+
+    for _ in 0..64 {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum();
+        if off_diagonal < 1e-30 {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for row in a.iter_mut() {
+                    let (akp, akq) = (row[p], row[q]);
+                    row[p] = c * akp - s * akq;
+                    row[q] = s * akp + c * akq;
+                }
+                let (upper, lower) = a.split_at_mut(q);
+                for (apk, aqk) in upper[p].iter_mut().zip(lower[0].iter_mut()) {
+                    let (x, y) = (*apk, *aqk);
+                    *apk = c * x - s * y;
+                    *aqk = s * x + c * y;
+                }
+                for row in v.iter_mut() {
+                    let (vkp, vkq) = (row[p], row[q]);
+                    row[p] = c * vkp - s * vkq;
+                    row[q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    ((0..n).map(|i| a[i][i]).collect(), v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shape::{Circle, Triangle};
+    use rand::SeedableRng;
+
+    #[test]
+    fn ignores_candidates_of_another_kind() {
+        let mut cma_es = CmaEs::new((64, 64), (0, 255), Constraints::default(), 0.1, 6, 3);
+        let mut rng = StdRng::seed_from_u64(1);
+        let start = Shape::Triangle(Triangle {
+            vertices: [[10.0, 10.0], [50.0, 12.0], [30.0, 40.0]],
+            color: [200, 100, 50, 255],
+        });
+        let mut candidates = cma_es.next_generation(vec![start], &[0.0], &mut rng);
+
+        // The best candidate is a shape of another kind.
+        candidates.push(Shape::Circle(Circle {
+            center: [32.0, 32.0],
+            radius: 8.0,
+            color: [10, 20, 30, 255],
+        }));
+        let mut fitness_scores = vec![-1.0; candidates.len()];
+        *fitness_scores.last_mut().unwrap() = 0.0;
+        let next = cma_es.next_generation(candidates, &fitness_scores, &mut rng);
+
+        assert_eq!(next.len(), 6);
+        assert!(next.iter().all(|shape| matches!(shape, Shape::Triangle(_))));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::optimizer::{DeStrategy, Optimizer};
+use crate::shape::{Constraints, MutationSteps, Shape};
+use rand::prelude::StdRng;
+use rand::seq::IteratorRandom;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+/// Differential evolution over the genes of a shape.
+///
+/// Every member of the population gets one trial per generation: a mutant built
+/// from the difference of two other members, mixed gene by gene with the member
+/// itself. The trial replaces the member if it is at least as fit. Differences
+/// are only taken between shapes of the same kind, so mixed populations work;
+/// a member without enough relatives of its kind is mutated instead.
+pub(crate) struct DifferentialEvolution {
+    strategy: DeStrategy,
+    differential_weight: f64,
+    crossover_rate: f64,
+    steps: MutationSteps,
+    scale: f64,
+    image_size: (u32, u32),
+    alpha_range: (u8, u8),
+    constraints: Constraints,
+    population: Vec<(Shape, f64)>,
+}
+
+impl DifferentialEvolution {
+    pub fn new(
+        strategy: DeStrategy,
+        differential_weight: f64,
+        crossover_rate: f64,
+        steps: MutationSteps,
+        image_size: (u32, u32),
+        alpha_range: (u8, u8),
+        constraints: Constraints,
+    ) -> Self {
+        Self {
+            strategy,
+            differential_weight,
+            crossover_rate,
+            steps,
+            scale: image_size.0.max(image_size.1) as f64,
+            image_size,
+            alpha_range,
+            constraints,
+            population: Vec::new(),
+        }
+    }
+
+    fn trial(&self, target: usize, rng: &mut impl Rng) -> Shape {
+        let shape = &self.population[target].0;
+        let same_kind = |i: &usize| {
+            *i != target
+                && std::mem::discriminant(&self.population[*i].0) == std::mem::discriminant(shape)
+        };
+
+        let base = match self.strategy {
+            DeStrategy::RandOne => None,
+            DeStrategy::BestOne => (0..self.population.len())
+                .filter(|i| *i == target || same_kind(i))
+                .max_by(|&a, &b| {
+                    self.population[a].1.partial_cmp(&self.population[b].1).unwrap()
+                }),
+        };
+        let donors = (0..self.population.len())
+            .filter(|i| same_kind(i) && Some(*i) != base)
+            .choose_multiple(rng, if base.is_some() { 2 } else { 3 });
+        if donors.len() < 2 || (base.is_none() && donors.len() < 3) {
+            return shape.mutate(
+                self.image_size,
+                1.0,
+                self.steps,
+                self.alpha_range,
+                &self.constraints,
+                rng,
+            );
+        }
+        let (base, r1, r2) = match base {
+            Some(base) => (base, donors[0], donors[1]),
+            None => (donors[0], donors[1], donors[2]),
+        };
+
+        let genes = |i: usize| self.population[i].0.genes(self.scale);
+        let (base, r1, r2, target_genes) = (genes(base), genes(r1), genes(r2), genes(target));
+        let forced = rng.gen_range(0..target_genes.len());
+        let trial: Vec<f64> = (0..target_genes.len())
+            .map(|j| {
+                if j == forced || rng.gen::<f64>() < self.crossover_rate {
+                    base[j] + self.differential_weight * (r1[j] - r2[j])
+                } else {
+                    target_genes[j]
+                }
+            })
+            .collect();
+        let trial = shape.with_genes(&trial, self.scale, self.alpha_range);
+        self.constraints.enforce(trial, shape, self.image_size)
+    }
+}
+
+impl Optimizer for DifferentialEvolution {
+    fn next_generation(
+        &mut self,
+        candidates: Vec<Shape>,
+        fitness_scores: &[f64],
+        rng: &mut StdRng,
+    ) -> Vec<Shape> {
+        if self.population.is_empty() {
+            self.population = candidates.into_iter().zip(fitness_scores.iter().copied()).collect();
+        } else {
+            for ((member, trial), &fitness) in
+                self.population.iter_mut().zip(candidates).zip(fitness_scores)
+            {
+                if fitness >= member.1 {
+                    *member = (trial, fitness);
+                }
+            }
+        }
+
+        let seeds: Vec<u64> = (0..self.population.len()).map(|_| rng.gen()).collect();
+        seeds
+            .into_par_iter()
+            .enumerate()
+            .map(|(target, seed)| self.trial(target, &mut StdRng::seed_from_u64(seed)))
+            .collect()
+    }
+}
@@ -0,0 +1,101 @@
+use crate::saliency::{blur, PositionSampler};
+use crate::shape::{Constraints, Shape, ShapeKind};
+use image::{ImageBuffer, Luma, RgbImage};
+use rand::Rng;
+
+/// Where the canvas is still furthest from the reference.
+///
+/// New shapes are centered on positions drawn in proportion to the remaining
+/// per-pixel error and sized to the blob of error around them, so the search
+/// for each shape starts where it can do the most good.
+pub(crate) struct ErrorGuide {
+    width: u32,
+    height: u32,
+    /// Summed-area table of the error, `width + 1` entries per row.
+    integral: Vec<f64>,
+    sampler: PositionSampler,
+}
+
+impl ErrorGuide {
+    /// Measures the squared RGB difference between `canvas_image` and
+    /// `reference_image`, scaled by the fitness `weights`.
+    pub fn new(canvas_image: &RgbImage, reference_image: &RgbImage, weights: &[f64]) -> Self {
+        let (width, height) = reference_image.dimensions();
+        let error: ImageBuffer<Luma<f32>, Vec<f32>> =
+            ImageBuffer::from_fn(width, height, |x, y| {
+                let canvas = canvas_image.get_pixel(x, y).0;
+                let reference = reference_image.get_pixel(x, y).0;
+                let squared: f64 = (0..3)
+                    .map(|ch| (canvas[ch] as f64 - reference[ch] as f64).powi(2))
+                    .sum();
+                Luma([(squared * weights[(y * width + x) as usize]) as f32])
+            });
+        // Lone noisy pixels are not worth a shape of their own.
+        let error: Vec<f64> = blur(&error)
+            .pixels()
+            .map(|p| p.0[0].max(0.0) as f64)
+            .collect();
+
+        let mut integral = vec![0.0; ((width + 1) * (height + 1)) as usize];
+        for y in 0..height as usize {
+            let mut row_sum = 0.0;
+            for x in 0..width as usize {
+                row_sum += error[y * width as usize + x];
+                integral[(y + 1) * (width as usize + 1) + x + 1] =
+                    integral[y * (width as usize + 1) + x + 1] + row_sum;
+            }
+        }
+
+        Self {
+            width,
+            height,
+            integral,
+            sampler: PositionSampler::new(&error, width),
+        }
+    }
+
+    /// Creates a random shape around a position drawn in proportion to the error,
+    /// extending about as far as the error around that position does.
+    pub fn random_shape(
+        &self,
+        kind: ShapeKind,
+        image_size: (u32, u32),
+        alpha_range: (u8, u8),
+        constraints: &Constraints,
+        rng: &mut impl Rng,
+    ) -> Shape {
+        let center = self.sampler.sample(rng);
+        let max_radius = (image_size.0.min(image_size.1) / 2).max(1) as i32;
+        let radius = self.blob_radius([center[0] as i32, center[1] as i32], max_radius);
+        Shape::random_near(kind, center, radius as f64, image_size, alpha_range, constraints, rng)
+    }
+
+    /// Doubles the radius around the pixel `center` for as long as the mean error inside it
+    /// stays at least half of that right at the center.
+    fn blob_radius(&self, center: [i32; 2], max_radius: i32) -> i32 {
+        let peak = self.mean_error(center, 1);
+        let mut radius = 2;
+        while radius * 2 <= max_radius && self.mean_error(center, radius * 2) >= 0.5 * peak {
+            radius *= 2;
+        }
+        radius.min(max_radius)
+    }
+
+    /// Mean error over the square of half-width `radius` around `center`,
+    /// clipped to the image.
+    fn mean_error(&self, center: [i32; 2], radius: i32) -> f64 {
+        let x0 = (center[0] - radius).clamp(0, self.width as i32) as usize;
+        let y0 = (center[1] - radius).clamp(0, self.height as i32) as usize;
+        let x1 = (center[0] + radius + 1).clamp(0, self.width as i32) as usize;
+        let y1 = (center[1] + radius + 1).clamp(0, self.height as i32) as usize;
+        if x0 >= x1 || y0 >= y1 {
+            return 0.0;
+        }
+        let stride = self.width as usize + 1;
+        let sum = self.integral[y1 * stride + x1]
+            - self.integral[y0 * stride + x1]
+            - self.integral[y1 * stride + x0]
+            + self.integral[y0 * stride + x0];
+        sum / ((x1 - x0) * (y1 - y0)) as f64
+    }
+}
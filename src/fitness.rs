@@ -0,0 +1,514 @@
+use crate::algo::blend_pixel;
+use crate::shape::Shape;
+use crate::ssim::StructuralErrors;
+use image::{GrayImage, RgbImage};
+use std::sync::OnceLock;
+
+/// How the difference between the canvas and the reference image is measured.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FitnessMetric {
+    /// Mean squared error over the sRGB channels.
+    Mse,
+    /// Mean squared CIE76 color difference, i.e. squared distance in CIELAB.
+    DeltaE76,
+    /// Mean squared CIEDE2000 color difference.
+    DeltaE2000,
+    /// One minus the structural similarity index, which favors matching edges
+    /// and texture over matching average colors.
+    Ssim,
+    /// Multi-scale SSIM over three levels of an image pyramid, which judges
+    /// structure at several sizes and brightness only at the coarsest.
+    MsSsim,
+}
+
+impl FitnessMetric {
+    pub const ALL: [FitnessMetric; 5] = [
+        FitnessMetric::Mse,
+        FitnessMetric::DeltaE76,
+        FitnessMetric::DeltaE2000,
+        FitnessMetric::Ssim,
+        FitnessMetric::MsSsim,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FitnessMetric::Mse => "RGB MSE",
+            FitnessMetric::DeltaE76 => "ΔE76",
+            FitnessMetric::DeltaE2000 => "ΔE2000",
+            FitnessMetric::Ssim => "SSIM",
+            FitnessMetric::MsSsim => "MS-SSIM",
+        }
+    }
+}
+
+/// Running error between the canvas and the reference image under a [`FitnessMetric`].
+///
+/// A shape only changes the pixels it covers, so the error of the canvas with a
+/// candidate drawn on top is the current error plus the change over those pixels.
+/// That lets candidates be scored without copying or rescanning the canvas.
+pub(crate) enum ErrorMap {
+    Pixel(PixelErrors),
+    Structural(StructuralErrors),
+}
+
+impl ErrorMap {
+    /// `weights` holds one non-negative weight per pixel in row-major order; see
+    /// [`pixel_weights`].
+    pub fn new(
+        canvas_image: &RgbImage,
+        reference_image: &RgbImage,
+        metric: FitnessMetric,
+        weights: &[f64],
+    ) -> Self {
+        match metric {
+            FitnessMetric::Mse | FitnessMetric::DeltaE76 | FitnessMetric::DeltaE2000 => {
+                ErrorMap::Pixel(PixelErrors::new(canvas_image, reference_image, metric, weights))
+            }
+            FitnessMetric::Ssim => ErrorMap::Structural(StructuralErrors::new(
+                canvas_image,
+                reference_image,
+                weights,
+                1,
+            )),
+            FitnessMetric::MsSsim => ErrorMap::Structural(StructuralErrors::new(
+                canvas_image,
+                reference_image,
+                weights,
+                3,
+            )),
+        }
+    }
+
+    /// Error the canvas would have with `shape` drawn on top.
+    pub fn error_with(&self, shape: &Shape, canvas_image: &RgbImage, reference_image: &RgbImage) -> f64 {
+        match self {
+            ErrorMap::Pixel(errors) => errors.error_with(shape, canvas_image, reference_image),
+            ErrorMap::Structural(errors) => errors.error_with(shape, canvas_image),
+        }
+    }
+
+    /// Error the canvas would have with each of `pixels` set to its color.
+    pub fn error_with_pixels(&self, pixels: &[([u32; 2], [u8; 3])], reference_image: &RgbImage) -> f64 {
+        match self {
+            ErrorMap::Pixel(errors) => errors.error_with_pixels(pixels, reference_image),
+            ErrorMap::Structural(errors) => errors.error_with_pixels(pixels),
+        }
+    }
+
+    /// Refreshes the error under `shape` after it has been drawn onto `canvas_image`.
+    pub fn update(&mut self, shape: &Shape, canvas_image: &RgbImage, reference_image: &RgbImage) {
+        match self {
+            ErrorMap::Pixel(errors) => errors.update(shape, canvas_image, reference_image),
+            ErrorMap::Structural(errors) => errors.update(shape, canvas_image),
+        }
+    }
+
+    /// Refreshes the error under `pixels` after each has been set to its color.
+    pub fn update_pixels(&mut self, pixels: &[([u32; 2], [u8; 3])], reference_image: &RgbImage) {
+        match self {
+            ErrorMap::Pixel(errors) => errors.update_pixels(pixels, reference_image),
+            ErrorMap::Structural(errors) => errors.update_pixels(pixels),
+        }
+    }
+}
+
+/// Converts a grayscale weight map into per-pixel weights, where white counts
+/// fully and black not at all. Without a map, or with an all-black one, every
+/// pixel counts the same.
+pub(crate) fn pixel_weights(weight_map: Option<&GrayImage>, (width, height): (u32, u32)) -> Vec<f64> {
+    if let Some(weight_map) = weight_map {
+        assert_eq!(weight_map.dimensions(), (width, height));
+        let weights: Vec<f64> = weight_map.pixels().map(|p| p.0[0] as f64 / 255.0).collect();
+        if weights.iter().any(|&w| w > 0.0) {
+            return weights;
+        }
+    }
+    vec![1.0; (width * height) as usize]
+}
+
+/// Unweighted mean squared error over the sRGB channels of two images.
+pub(crate) fn mse(canvas_image: &RgbImage, reference_image: &RgbImage) -> f64 {
+    let sum: f64 = canvas_image
+        .as_raw()
+        .iter()
+        .zip(reference_image.as_raw())
+        .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+        .sum();
+    sum / canvas_image.as_raw().len() as f64
+}
+
+/// Peak signal-to-noise ratio in decibels for a mean squared error of 8-bit values.
+pub(crate) fn psnr(mse: f64) -> f64 {
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+/// Returns the color that, drawn with the shape's alpha over `canvas_image`,
+/// minimizes the weighted squared error to the reference under the shape. For
+/// an opaque shape that is simply the weighted mean of the reference beneath it,
+/// apart from its anti-aliased edges. Exact for [`FitnessMetric::Mse`] up to
+/// rounding and a close approximation for the others.
+pub(crate) fn optimal_color(
+    shape: &Shape,
+    canvas_image: &RgbImage,
+    reference_image: &RgbImage,
+    weights: &[f64],
+) -> [u8; 3] {
+    let color = shape.color();
+    let alpha = color[3] as f64 / 255.0;
+    let width = canvas_image.width();
+
+    // Solve sum(w * a * (a * c + (1 - a) * canvas - reference)) = 0 for c, where
+    // a is alpha times the coverage of each pixel.
+    let mut numerator = [0.0; 3];
+    let mut denominator = 0.0;
+    shape.rasterize(canvas_image.dimensions(), |x, y, coverage| {
+        let a = alpha * coverage;
+        let gain = weights[(y * width + x) as usize] * a;
+        let reference = reference_image.get_pixel(x, y).0;
+        let canvas = canvas_image.get_pixel(x, y).0;
+        denominator += gain * a;
+        for ch in 0..3 {
+            numerator[ch] += gain * (reference[ch] as f64 - (1.0 - a) * canvas[ch] as f64);
+        }
+    });
+
+    if denominator == 0.0 {
+        return [color[0], color[1], color[2]];
+    }
+    numerator.map(|n| (n / denominator).round().clamp(0.0, 255.0) as u8)
+}
+
+/// Weighted error between each canvas pixel and its reference pixel, for metrics
+/// that compare pixels independently.
+pub(crate) struct PixelErrors {
+    width: u32,
+    metric: FitnessMetric,
+    reference_lab: Vec<[f64; 3]>,
+    weights: Vec<f64>,
+    weight_total: f64,
+    errors: Vec<f64>,
+    total: f64,
+}
+
+impl PixelErrors {
+    pub fn new(
+        canvas_image: &RgbImage,
+        reference_image: &RgbImage,
+        metric: FitnessMetric,
+        weights: &[f64],
+    ) -> Self {
+        assert_eq!(canvas_image.dimensions(), reference_image.dimensions());
+
+        let width = canvas_image.width();
+        let reference_lab = match metric {
+            FitnessMetric::DeltaE76 | FitnessMetric::DeltaE2000 => {
+                reference_image.pixels().map(|p| srgb_to_lab(p.0)).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        let mut error_map = Self {
+            width,
+            metric,
+            reference_lab,
+            weights: weights.to_vec(),
+            weight_total: weights.iter().sum(),
+            errors: Vec::new(),
+            total: 0.0,
+        };
+        if error_map.weight_total == 0.0 {
+            // Nothing left to weigh, e.g. after downscaling, so count every pixel.
+            error_map.weights.fill(1.0);
+            error_map.weight_total = error_map.weights.len() as f64;
+        }
+        error_map.errors = canvas_image
+            .enumerate_pixels()
+            .map(|(x, y, p)| error_map.pixel_error(p.0, x, y, reference_image))
+            .collect();
+        error_map.total = error_map.errors.iter().sum();
+        error_map
+    }
+
+    /// Mean error the canvas would have with `shape` drawn on top.
+    pub fn error_with(&self, shape: &Shape, canvas_image: &RgbImage, reference_image: &RgbImage) -> f64 {
+        let color = shape.color();
+        let mut delta = 0.0;
+        shape.rasterize(self.dimensions(), |x, y, coverage| {
+            let blended = blend_pixel(canvas_image.get_pixel(x, y).0, color, coverage);
+            let error = self.pixel_error(blended, x, y, reference_image);
+            delta += error - self.errors[self.index(x, y)];
+        });
+        (self.total + delta) * self.scale()
+    }
+
+    /// Mean error the canvas would have with each of `pixels` set to its color.
+    pub fn error_with_pixels(&self, pixels: &[([u32; 2], [u8; 3])], reference_image: &RgbImage) -> f64 {
+        let delta: f64 = pixels
+            .iter()
+            .map(|&([x, y], rgb)| {
+                self.pixel_error(rgb, x, y, reference_image) - self.errors[self.index(x, y)]
+            })
+            .sum();
+        (self.total + delta) * self.scale()
+    }
+
+    /// Refreshes the errors under `shape` after it has been drawn onto `canvas_image`.
+    pub fn update(&mut self, shape: &Shape, canvas_image: &RgbImage, reference_image: &RgbImage) {
+        shape.rasterize(self.dimensions(), |x, y, _| {
+            let i = self.index(x, y);
+            let error = self.pixel_error(canvas_image.get_pixel(x, y).0, x, y, reference_image);
+            self.total += error - self.errors[i];
+            self.errors[i] = error;
+        });
+    }
+
+    /// Refreshes the errors under `pixels` after each has been set to its color.
+    pub fn update_pixels(&mut self, pixels: &[([u32; 2], [u8; 3])], reference_image: &RgbImage) {
+        for &([x, y], rgb) in pixels {
+            let i = self.index(x, y);
+            let error = self.pixel_error(rgb, x, y, reference_image);
+            self.total += error - self.errors[i];
+            self.errors[i] = error;
+        }
+    }
+
+    fn pixel_error(&self, rgb: [u8; 3], x: u32, y: u32, reference_image: &RgbImage) -> f64 {
+        self.weights[self.index(x, y)] * self.unweighted_error(rgb, x, y, reference_image)
+    }
+
+    fn unweighted_error(&self, rgb: [u8; 3], x: u32, y: u32, reference_image: &RgbImage) -> f64 {
+        match self.metric {
+            FitnessMetric::DeltaE76 => {
+                let lab = srgb_to_lab(rgb);
+                let reference = self.reference_lab[self.index(x, y)];
+                (lab[0] - reference[0]).powi(2)
+                    + (lab[1] - reference[1]).powi(2)
+                    + (lab[2] - reference[2]).powi(2)
+            }
+            FitnessMetric::DeltaE2000 => {
+                delta_e2000_squared(srgb_to_lab(rgb), self.reference_lab[self.index(x, y)])
+            }
+            _ => {
+                let reference = reference_image.get_pixel(x, y).0;
+                (rgb[0] as f64 - reference[0] as f64).powi(2)
+                    + (rgb[1] as f64 - reference[1] as f64).powi(2)
+                    + (rgb[2] as f64 - reference[2] as f64).powi(2)
+            }
+        }
+    }
+
+    /// Turns a sum of pixel errors into a weighted mean. MSE is averaged per
+    /// channel to match how it is conventionally reported; color differences per pixel.
+    fn scale(&self) -> f64 {
+        match self.metric {
+            FitnessMetric::DeltaE76 | FitnessMetric::DeltaE2000 => 1.0 / self.weight_total,
+            _ => 1.0 / (self.weight_total * 3.0),
+        }
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.weights.len() as u32 / self.width)
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+}
+
+/// Converts an sRGB color to CIELAB under the D65 white point.
+fn srgb_to_lab(rgb: [u8; 3]) -> [f64; 3] {
+    static LINEAR: OnceLock<[f64; 256]> = OnceLock::new();
+    let linear = LINEAR.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, value) in table.iter_mut().enumerate() {
+            let c = i as f64 / 255.0;
+            *value = if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            };
+        }
+        table
+    });
+
+    let r = linear[rgb[0] as usize];
+    let g = linear[rgb[1] as usize];
+    let b = linear[rgb[2] as usize];
+
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+
+    let f = |t: f64| {
+        const DELTA: f64 = 6.0 / 29.0;
+        if t > DELTA * DELTA * DELTA {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Squared CIEDE2000 difference, following Sharma, Wu and Dalal (2005).
+fn delta_e2000_squared(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+    const POW25_7: f64 = 6_103_515_625.0;
+
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+
+    let c_bar = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+    let c_bar7 = c_bar.powi(7);
+    let g = 0.5 * (1.0 - (c_bar7 / (c_bar7 + POW25_7)).sqrt());
+
+    let a1p = (1.0 + g) * a1;
+    let a2p = (1.0 + g) * a2;
+    let c1p = (a1p * a1p + b1 * b1).sqrt();
+    let c2p = (a2p * a2p + b2 * b2).sqrt();
+    let hue = |b: f64, a: f64| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let h1p = hue(b1, a1p);
+    let h2p = hue(b2, a2p);
+
+    let delta_lp = l2 - l1;
+    let delta_cp = c2p - c1p;
+    let delta_hp = if c1p * c2p == 0.0 {
+        0.0
+    } else if (h2p - h1p).abs() <= 180.0 {
+        h2p - h1p
+    } else if h2p - h1p > 180.0 {
+        h2p - h1p - 360.0
+    } else {
+        h2p - h1p + 360.0
+    };
+    let delta_big_hp = 2.0 * (c1p * c2p).sqrt() * (delta_hp / 2.0).to_radians().sin();
+
+    let l_bar_p = (l1 + l2) / 2.0;
+    let c_bar_p = (c1p + c2p) / 2.0;
+    let h_bar_p = if c1p * c2p == 0.0 {
+        h1p + h2p
+    } else if (h1p - h2p).abs() <= 180.0 {
+        (h1p + h2p) / 2.0
+    } else if h1p + h2p < 360.0 {
+        (h1p + h2p + 360.0) / 2.0
+    } else {
+        (h1p + h2p - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar_p - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_bar_p).to_radians().cos()
+        + 0.32 * (3.0 * h_bar_p + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_bar_p - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((h_bar_p - 275.0) / 25.0).powi(2)).exp();
+    let c_bar_p7 = c_bar_p.powi(7);
+    let r_c = 2.0 * (c_bar_p7 / (c_bar_p7 + POW25_7)).sqrt();
+    let l_offset = (l_bar_p - 50.0).powi(2);
+    let s_l = 1.0 + 0.015 * l_offset / (20.0 + l_offset).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar_p;
+    let s_h = 1.0 + 0.015 * c_bar_p * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let dl = delta_lp / s_l;
+    let dc = delta_cp / s_c;
+    let dh = delta_big_hp / s_h;
+    (dl * dl + dc * dc + dh * dh + r_t * dc * dh).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::draw_shape_onto_canvas;
+    use crate::shape::{Ellipse, Triangle};
+    use image::Rgb;
+
+    const SIZE: u32 = 48;
+
+    fn current_error(error_map: &ErrorMap, reference_image: &RgbImage) -> f64 {
+        error_map.error_with_pixels(&[], reference_image)
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn incremental_errors_match_recomputing() {
+        let reference_image = RgbImage::from_fn(SIZE, SIZE, |x, y| {
+            Rgb([(x * 5) as u8, (y * 5) as u8, ((x + y) * 3) as u8])
+        });
+        let weights: Vec<f64> = (0..SIZE * SIZE).map(|i| (i % 7) as f64 / 6.0).collect();
+        let shapes = [
+            Shape::Triangle(Triangle {
+                vertices: [[3.5, 2.0], [44.2, 10.7], [20.1, 40.3]],
+                color: [220, 40, 90, 180],
+            }),
+            Shape::Ellipse(Ellipse {
+                center: [30.4, 28.9],
+                radii: [14.2, 6.6],
+                angle: 35.0,
+                color: [20, 200, 140, 255],
+            }),
+            Shape::Triangle(Triangle {
+                vertices: [[-4.0, 30.0], [25.5, 50.0], [12.0, 18.25]],
+                color: [250, 250, 10, 90],
+            }),
+        ];
+        // Distinct pixels, as the callers pass them.
+        let pixels: Vec<([u32; 2], [u8; 3])> = (0..SIZE)
+            .map(|x| ([x, x * 7 % SIZE], [(x * 5) as u8, 200, 17]))
+            .collect();
+
+        for metric in FitnessMetric::ALL {
+            let mut canvas = RgbImage::new(SIZE, SIZE);
+            let mut error_map = ErrorMap::new(&canvas, &reference_image, metric, &weights);
+            for shape in &shapes {
+                let predicted = error_map.error_with(shape, &canvas, &reference_image);
+                draw_shape_onto_canvas(&mut canvas, shape);
+                error_map.update(shape, &canvas, &reference_image);
+                let fresh = ErrorMap::new(&canvas, &reference_image, metric, &weights);
+                assert_close(predicted, current_error(&fresh, &reference_image));
+                assert_close(
+                    current_error(&error_map, &reference_image),
+                    current_error(&fresh, &reference_image),
+                );
+            }
+
+            let predicted = error_map.error_with_pixels(&pixels, &reference_image);
+            error_map.update_pixels(&pixels, &reference_image);
+            for &([x, y], rgb) in &pixels {
+                canvas.put_pixel(x, y, Rgb(rgb));
+            }
+            let fresh = ErrorMap::new(&canvas, &reference_image, metric, &weights);
+            assert_close(predicted, current_error(&fresh, &reference_image));
+            assert_close(
+                current_error(&error_map, &reference_image),
+                current_error(&fresh, &reference_image),
+            );
+        }
+    }
+
+    #[test]
+    fn delta_e2000_matches_sharma() {
+        // Pairs from Sharma, Wu and Dalal's CIEDE2000 test data.
+        let pairs = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+            ([50.0, 2.8361, -74.0200], [50.0, 0.0, -82.7485], 3.4412),
+            ([50.0, -1.3802, -84.2814], [50.0, 0.0, -82.7485], 1.0000),
+            ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387], 1.2644),
+        ];
+        for (lab1, lab2, expected) in pairs {
+            let delta_e = delta_e2000_squared(lab1, lab2).sqrt();
+            assert!((delta_e - expected).abs() < 1e-4, "{delta_e} != {expected}");
+        }
+    }
+}
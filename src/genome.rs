@@ -0,0 +1,221 @@
+use crate::algo::{is_degenerate, pixel_box, redrawn_pixels, union_box, AlgorithmParams};
+use crate::fitness::{mse, psnr, ErrorMap};
+use crate::shape::{Constraints, Shape};
+use image::RgbImage;
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+/// A change to one shape of the genome.
+enum Edit {
+    Insert(usize, Shape),
+    Delete(usize),
+    /// Moves the shape at the first index to the second in drawing order.
+    Reorder(usize, usize),
+    /// Moves or recolors the shape at the index.
+    Replace(usize, Shape),
+}
+
+impl Edit {
+    fn random(
+        genome: &[Shape],
+        params: &AlgorithmParams,
+        constraints: &Constraints,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let image_size = (params.image_size, params.image_size);
+        let alpha_range = (params.min_alpha, params.max_alpha);
+        let steps = params.mutation_steps;
+        let can_insert = genome.len() < params.num_triangles;
+        let choice = match (genome.is_empty(), can_insert) {
+            (true, _) => 0,
+            (false, true) => rng.gen_range(0..5),
+            (false, false) => rng.gen_range(1..5),
+        };
+
+        let index = rng.gen_range(0..genome.len().max(1));
+        match choice {
+            0 => Edit::Insert(
+                rng.gen_range(0..=genome.len()),
+                Shape::random(params.shape_kind, image_size, alpha_range, constraints, rng),
+            ),
+            1 => Edit::Delete(index),
+            2 => Edit::Reorder(index, rng.gen_range(0..genome.len())),
+            3 => {
+                let shape = &genome[index];
+                let mut moved = shape.mutate(image_size, 1.0, steps, alpha_range, constraints, rng);
+                *moved.color_mut() = shape.color();
+                Edit::Replace(index, moved)
+            }
+            _ => {
+                let shape = &genome[index];
+                // Only the color is kept, so the geometry need not be allowed.
+                let unconstrained = Constraints::default();
+                let mut recolored = shape.clone();
+                *recolored.color_mut() = shape
+                    .mutate(image_size, 1.0, steps, alpha_range, &unconstrained, rng)
+                    .color();
+                Edit::Replace(index, recolored)
+            }
+        }
+    }
+
+    /// The genome after this edit, in drawing order.
+    fn apply<'a>(&'a self, genome: &'a [Shape]) -> Vec<&'a Shape> {
+        let mut shapes: Vec<&Shape> = genome.iter().collect();
+        match self {
+            Edit::Insert(index, shape) => shapes.insert(*index, shape),
+            Edit::Delete(index) => {
+                shapes.remove(*index);
+            }
+            Edit::Reorder(from, to) => {
+                let shape = shapes.remove(*from);
+                shapes.insert(*to, shape);
+            }
+            Edit::Replace(index, shape) => shapes[*index] = shape,
+        }
+        shapes
+    }
+
+    /// The new shape, if the edit brings one into the genome.
+    fn new_shape(&self) -> Option<&Shape> {
+        match self {
+            Edit::Insert(_, shape) | Edit::Replace(_, shape) => Some(shape),
+            Edit::Delete(_) | Edit::Reorder(..) => None,
+        }
+    }
+
+    /// Bounding box of the pixels the edit can change.
+    fn bounding_box(&self, genome: &[Shape]) -> ([f64; 2], [f64; 2]) {
+        match self {
+            Edit::Insert(_, shape) => shape.bounding_box(),
+            Edit::Delete(index) | Edit::Reorder(index, _) => genome[*index].bounding_box(),
+            Edit::Replace(index, shape) => {
+                union_box(genome[*index].bounding_box(), shape.bounding_box())
+            }
+        }
+    }
+}
+
+/// Evolves all shapes of the image at once, in the style of Roger Alsing's
+/// "Evolution of Mona Lisa", instead of placing them one at a time.
+///
+/// The genome is the complete ordered list of shapes, starting out empty and
+/// holding at most `num_triangles`. Every generation, `population_size`
+/// offspring each insert, delete, reorder, move or recolor one shape, and the
+/// fittest replaces the genome unless it is worse. The run lasts
+/// `num_triangles * num_generations` generations, so it does about as many
+/// evaluations as placing the shapes one at a time would.
+///
+/// With `coarse_to_fine`, the size of new and changed shapes is limited by how
+/// far the run has got rather than by how many shapes there are. Likewise,
+/// `stagnation_generations` is scaled by `num_triangles`: the run ends once
+/// that many times its generations pass without improvement.
+///
+/// `on_generation` is called with the generation index, the current genome and
+/// the shapes the offspring brought in; `on_improvement` with the genome, its
+/// canvas and its fitness whenever the fitness improves.
+pub(crate) fn evolve_genome(
+    params: &AlgorithmParams,
+    reference_image: &RgbImage,
+    weights: &[f64],
+    rng: &mut StdRng,
+    stop: impl Fn() -> bool,
+    mut on_generation: impl FnMut(usize, &[Shape], Vec<Shape>),
+    mut on_improvement: impl FnMut(&[Shape], &RgbImage, f64),
+) -> Vec<Shape> {
+    let image_size = reference_image.dimensions();
+    let degeneracy_threshold = params.degeneracy_threshold.unwrap_or(0.0);
+    let mut genome: Vec<Shape> = Vec::new();
+    let mut canvas_image = RgbImage::new(image_size.0, image_size.1);
+    let mut error_map = ErrorMap::new(
+        &canvas_image,
+        reference_image,
+        params.fitness_metric,
+        weights,
+    );
+    let mut error = error_map.error_with_pixels(&[], reference_image);
+    let mut last_improvement = 0;
+
+    let num_generations = params.num_triangles * params.num_generations;
+    let stagnation_generations = params
+        .stagnation_generations
+        .map(|n| n * params.num_triangles.max(1));
+    for generation_index in 0..num_generations {
+        if stop() {
+            break;
+        }
+        if stagnation_generations.is_some_and(|n| generation_index - last_improvement >= n)
+        {
+            break;
+        }
+
+        let constraints = params.constraints_at(generation_index as f64 / num_generations as f64);
+        let seeds: Vec<u64> = (0..params.population_size).map(|_| rng.gen()).collect();
+        let offspring: Vec<_> = seeds
+            .into_par_iter()
+            .map(|seed| {
+                let mut thread_rng = StdRng::seed_from_u64(seed);
+                let edit = Edit::random(&genome, params, &constraints, &mut thread_rng);
+                if degeneracy_threshold > 0.0
+                    && edit
+                        .new_shape()
+                        .is_some_and(|shape| is_degenerate(shape, degeneracy_threshold))
+                {
+                    return (edit, Vec::new(), f64::MAX);
+                }
+                let pixels = redrawn_pixels(
+                    &canvas_image,
+                    None,
+                    edit.apply(&genome),
+                    pixel_box(edit.bounding_box(&genome), image_size),
+                );
+                let error = error_map.error_with_pixels(&pixels, reference_image);
+                (edit, pixels, error)
+            })
+            .collect();
+
+        on_generation(
+            generation_index,
+            &genome,
+            offspring
+                .iter()
+                .filter_map(|(edit, _, _)| edit.new_shape().cloned())
+                .collect(),
+        );
+
+        let Some((edit, pixels, best_error)) = offspring
+            .into_iter()
+            .min_by(|(_, _, e1), (_, _, e2)| e1.partial_cmp(e2).unwrap())
+        else {
+            continue;
+        };
+        // Neutral edits are kept too, which lets the genome drift and shed
+        // shapes that no longer show.
+        if best_error > error {
+            continue;
+        }
+
+        genome = edit.apply(&genome).into_iter().cloned().collect();
+        error_map.update_pixels(&pixels, reference_image);
+        for ([x, y], rgb) in pixels {
+            canvas_image.put_pixel(x, y, image::Rgb(rgb));
+        }
+        if best_error < error {
+            last_improvement = generation_index;
+            on_improvement(&genome, &canvas_image, -best_error);
+        }
+        error = best_error;
+
+        if params.target_mse.is_some() || params.target_psnr.is_some() {
+            let mse = mse(&canvas_image, reference_image);
+            if params.target_mse.is_some_and(|target| mse <= target)
+                || params.target_psnr.is_some_and(|target| psnr(mse) >= target)
+            {
+                break;
+            }
+        }
+    }
+
+    genome
+}
@@ -1,23 +1,35 @@
-use crate::algo::{draw_triangle_onto_canvas, run_algorithm, AlgorithmParams, Progress};
 use eframe::egui;
-use image::RgbImage;
+use image::{GrayImage, Luma, RgbImage};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use svg::Document;
+use triklops::algo::{
+    draw_shape_onto_canvas, new_document, run_algorithm, AlgorithmParams, EvolutionMode, Progress,
+};
+use triklops::fitness::FitnessMetric;
+use triklops::optimizer::{
+    CoolingSchedule, Crossover, DeStrategy, OptimizerKind, Selection, StepSchedule,
+};
+use triklops::shape::ShapeKind;
 
 pub struct TriKlopsApp {
     params: AlgorithmParams,
     reference_image_path: String,
     progress: Arc<Mutex<Progress>>,
     reference_image: Option<RgbImage>,
+    weight_map: Option<GrayImage>,
+    paint_weights: bool,
+    brush_radius: f32,
     current_canvas: Arc<Mutex<Option<RgbImage>>>,
     current_svg: Arc<Mutex<Option<Document>>>,
     use_custom_seed: bool,
     custom_seed: String,
     use_degeneracy_threshold: bool,
     degeneracy_threshold_value: f32,
+    use_prune_threshold: bool,
+    prune_threshold_value: f64,
 }
 
 impl Default for TriKlopsApp {
@@ -27,12 +39,17 @@ impl Default for TriKlopsApp {
             reference_image_path: String::new(),
             progress: Arc::new(Mutex::new(Progress::default())),
             reference_image: None,
+            weight_map: None,
+            paint_weights: false,
+            brush_radius: 12.0,
             current_canvas: Arc::new(Mutex::new(None)),
             current_svg: Arc::new(Mutex::new(None)),
             use_custom_seed: false,
             custom_seed: String::new(),
             use_degeneracy_threshold: false,
             degeneracy_threshold_value: 1.0,
+            use_prune_threshold: false,
+            prune_threshold_value: 0.0001,
         }
     }
 }
@@ -49,7 +66,7 @@ impl TriKlopsApp {
         let progress = self.progress.lock().unwrap();
 
         if !progress.current_generation.is_empty() {
-            // Create a full-sized image with all triangles from current generation
+            // Create a full-sized image with all shapes from current generation
             let mut generation_image = RgbImage::new(self.params.image_size, self.params.image_size);
 
             // Start with current canvas as base
@@ -59,9 +76,9 @@ impl TriKlopsApp {
                 }
             }
 
-            // Draw all triangles from current generation on top
-            for triangle in &progress.current_generation {
-                draw_triangle_onto_canvas(&mut generation_image, triangle);
+            // Draw all shapes from current generation on top
+            for shape in &progress.current_generation {
+                draw_shape_onto_canvas(&mut generation_image, shape);
             }
 
             // Convert to egui texture
@@ -111,6 +128,43 @@ impl TriKlopsApp {
         }
     }
 
+    fn load_weight_map(&mut self, path: &Path) {
+        if let Ok(img) = image::open(path) {
+            self.weight_map = Some(
+                img.resize_exact(
+                    self.params.image_size,
+                    self.params.image_size,
+                    image::imageops::FilterType::Lanczos3,
+                )
+                .to_luma8(),
+            );
+        }
+    }
+
+    /// Paints a round brush stroke of `value` into the weight map, creating a
+    /// neutral gray map first if there is none.
+    fn paint_weight_map(&mut self, center: egui::Pos2, value: u8) {
+        let size = self.params.image_size;
+        let weight_map = self
+            .weight_map
+            .get_or_insert_with(|| GrayImage::from_pixel(size, size, Luma([128])));
+
+        let radius = self.brush_radius;
+        let x_min = (center.x - radius).floor().max(0.0) as u32;
+        let y_min = (center.y - radius).floor().max(0.0) as u32;
+        let x_max = ((center.x + radius).ceil().max(0.0) as u32).min(size - 1);
+        let y_max = ((center.y + radius).ceil().max(0.0) as u32).min(size - 1);
+        for y in y_min..=y_max {
+            for x in x_min..=x_max {
+                let dx = x as f32 - center.x;
+                let dy = y as f32 - center.y;
+                if dx * dx + dy * dy <= radius * radius {
+                    weight_map.put_pixel(x, y, Luma([value]));
+                }
+            }
+        }
+    }
+
     fn start_algorithm(&mut self, ctx: &egui::Context) {
         if self.reference_image.is_none() {
             // This check is important, though the button should also be disabled.
@@ -120,6 +174,7 @@ impl TriKlopsApp {
 
         let params = self.params.clone();
         let reference_image = self.reference_image.clone().unwrap(); // Safe due to check above
+        let weight_map = self.weight_map.clone();
         let output_path = self.get_output_path();
         let progress_arc = Arc::clone(&self.progress);
         let current_canvas_arc = Arc::clone(&self.current_canvas);
@@ -144,32 +199,21 @@ impl TriKlopsApp {
 
         {
             let mut svg = current_svg_arc.lock().unwrap();
-            *svg = Some(
-                Document::new()
-                    .set("width", params.image_size)
-                    .set("height", params.image_size)
-                    .set("viewBox", (0, 0, params.image_size, params.image_size))
-                    .set("overflow", "hidden")
-                    .add(
-                        svg::node::element::Rectangle::new()
-                            .set("x", 0)
-                            .set("y", 0)
-                            .set("width", params.image_size)
-                            .set("height", params.image_size)
-                            .set("fill", "black"),
-                    ),
-            );
+            *svg = Some(new_document(&params, params.seed));
         }
 
         thread::spawn(move || {
-            run_algorithm(
+            if let Err(err) = run_algorithm(
                 params,
                 reference_image,
-                output_path,
+                weight_map,
+                output_path.clone(),
                 progress_arc,
                 current_canvas_arc,
                 current_svg_arc,
-            );
+            ) {
+                eprintln!("Failed to save {output_path}: {err}");
+            }
             ctx_clone.request_repaint();
         });
     }
@@ -197,13 +241,15 @@ impl eframe::App for TriKlopsApp {
             self.params.degeneracy_threshold = None;
         }
 
+        self.params.prune_threshold = self.use_prune_threshold.then_some(self.prune_threshold_value);
+
         let progress_data = self.progress.lock().unwrap().clone();
         let has_reference_image = self.reference_image.is_some();
 
         egui::SidePanel::left("controls")
             .exact_width(230.0)
             .show(ctx, |ui| {
-                ui.vertical(|ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
                     ui.add_space(16.0);
 
                     // Load Reference Image button (conditionally enabled, centered)
@@ -222,6 +268,32 @@ impl eframe::App for TriKlopsApp {
                         });
                     });
 
+                    ui.add_space(8.0);
+
+                    // Weight map controls (conditionally enabled)
+                    ui.add_enabled_ui(has_reference_image && !progress_data.is_running, |ui| {
+                        ui.horizontal(|ui| {
+                            if ui.button("Load Weight Map...").clicked() {
+                                if let Some(path) = rfd::FileDialog::new()
+                                    .add_filter("Images", &["png", "jpg", "jpeg", "bmp", "gif"])
+                                    .pick_file()
+                                {
+                                    self.load_weight_map(&path);
+                                }
+                            }
+                            if ui.button("Clear").clicked() {
+                                self.weight_map = None;
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut self.paint_weights, "Paint Weights");
+                            ui.add(
+                                egui::DragValue::new(&mut self.brush_radius)
+                                    .range(1.0..=128.0)
+                                    .prefix("Brush: "),
+                            );
+                        });
+                    });
 
                     ui.add_space(12.0);
                     ui.separator();
@@ -232,6 +304,34 @@ impl eframe::App for TriKlopsApp {
                         egui::Grid::new("params_grid")
                             .spacing(egui::vec2(8.0, 8.0))
                             .show(ui, |ui| {
+                                ui.label("Mode:");
+                                egui::ComboBox::from_id_salt("evolution_mode")
+                                    .selected_text(self.params.evolution_mode.name())
+                                    .show_ui(ui, |ui| {
+                                        for mode in EvolutionMode::ALL {
+                                            ui.selectable_value(
+                                                &mut self.params.evolution_mode,
+                                                mode,
+                                                mode.name(),
+                                            );
+                                        }
+                                    });
+                                ui.end_row();
+
+                                ui.label("Shape:");
+                                egui::ComboBox::from_id_salt("shape_kind")
+                                    .selected_text(self.params.shape_kind.name())
+                                    .show_ui(ui, |ui| {
+                                        for kind in ShapeKind::ALL {
+                                            ui.selectable_value(
+                                                &mut self.params.shape_kind,
+                                                kind,
+                                                kind.name(),
+                                            );
+                                        }
+                                    });
+                                ui.end_row();
+
                                 ui.label("Triangles:");
                                 ui.add(egui::DragValue::new(&mut self.params.num_triangles).speed(1.0));
                                 ui.end_row();
@@ -244,12 +344,18 @@ impl eframe::App for TriKlopsApp {
 
                                 ui.label("Population Size:");
                                 ui.add(
-                                    egui::DragValue::new(&mut self.params.population_size).speed(1.0),
+                                    egui::DragValue::new(&mut self.params.population_size)
+                                        .range(1..=usize::MAX)
+                                        .speed(1.0),
                                 );
                                 ui.end_row();
 
                                 ui.label("Selected:");
-                                ui.add(egui::DragValue::new(&mut self.params.num_selected).speed(1.0));
+                                ui.add(
+                                    egui::DragValue::new(&mut self.params.num_selected)
+                                        .range(1..=self.params.population_size)
+                                        .speed(1.0),
+                                );
                                 ui.end_row();
 
                                 ui.label("Mutation Rate:");
@@ -258,6 +364,353 @@ impl eframe::App for TriKlopsApp {
                                 );
                                 ui.end_row();
 
+                                ui.label("Position Step:");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.params.mutation_steps.position)
+                                        .range(0.0..=1.0)
+                                        .speed(0.005),
+                                );
+                                ui.end_row();
+
+                                ui.label("Angle Step:");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.params.mutation_steps.angle)
+                                        .range(0.0..=90.0)
+                                        .speed(0.5),
+                                );
+                                ui.end_row();
+
+                                ui.label("Color Step:");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.params.mutation_steps.color)
+                                        .range(0.0..=255.0)
+                                        .speed(0.5),
+                                );
+                                ui.end_row();
+
+                                ui.label("Step Schedule:");
+                                egui::ComboBox::from_id_salt("step_schedule")
+                                    .selected_text(self.params.step_schedule.name())
+                                    .show_ui(ui, |ui| {
+                                        for schedule in StepSchedule::ALL {
+                                            ui.selectable_value(
+                                                &mut self.params.step_schedule,
+                                                schedule,
+                                                schedule.name(),
+                                            );
+                                        }
+                                    });
+                                ui.end_row();
+
+                                ui.label("Step Shrink:");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.params.step_shrink)
+                                        .range(0.0..=1.0)
+                                        .speed(0.01),
+                                );
+                                ui.end_row();
+
+                                ui.label("Fitness:");
+                                egui::ComboBox::from_id_salt("fitness_metric")
+                                    .selected_text(self.params.fitness_metric.name())
+                                    .show_ui(ui, |ui| {
+                                        for metric in FitnessMetric::ALL {
+                                            ui.selectable_value(
+                                                &mut self.params.fitness_metric,
+                                                metric,
+                                                metric.name(),
+                                            );
+                                        }
+                                    });
+                                ui.end_row();
+                            });
+
+                        egui::CollapsingHeader::new("Optimizer")
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                egui::Grid::new("optimizer_grid")
+                                    .spacing(egui::vec2(8.0, 8.0))
+                                    .show(ui, |ui| {
+                                        ui.label("Optimizer:");
+                                        egui::ComboBox::from_id_salt("optimizer")
+                                            .selected_text(self.params.optimizer.name())
+                                            .show_ui(ui, |ui| {
+                                                for kind in OptimizerKind::ALL {
+                                                    ui.selectable_value(
+                                                        &mut self.params.optimizer,
+                                                        kind,
+                                                        kind.name(),
+                                                    );
+                                                }
+                                            });
+                                        ui.end_row();
+
+                                        if self.params.optimizer == OptimizerKind::Genetic {
+                                            ui.label("Selection:");
+                                            egui::ComboBox::from_id_salt("selection")
+                                                .selected_text(self.params.selection.name())
+                                                .show_ui(ui, |ui| {
+                                                    for selection in Selection::ALL {
+                                                        ui.selectable_value(
+                                                            &mut self.params.selection,
+                                                            selection,
+                                                            selection.name(),
+                                                        );
+                                                    }
+                                                });
+                                            ui.end_row();
+
+                                            if self.params.selection == Selection::Tournament {
+                                                ui.label("Tournament Size:");
+                                                ui.add(
+                                                    egui::DragValue::new(&mut self.params.tournament_size)
+                                                        .range(1..=64)
+                                                        .speed(1.0),
+                                                );
+                                                ui.end_row();
+                                            }
+
+                                            ui.label("Elitism:");
+                                            ui.add(egui::DragValue::new(&mut self.params.elitism).speed(1.0));
+                                            ui.end_row();
+
+                                            ui.label("Crossover:");
+                                            egui::ComboBox::from_id_salt("crossover")
+                                                .selected_text(self.params.crossover.name())
+                                                .show_ui(ui, |ui| {
+                                                    for crossover in Crossover::ALL {
+                                                        ui.selectable_value(
+                                                            &mut self.params.crossover,
+                                                            crossover,
+                                                            crossover.name(),
+                                                        );
+                                                    }
+                                                });
+                                            ui.end_row();
+                                        }
+
+                                        if self.params.optimizer == OptimizerKind::SimulatedAnnealing {
+                                            ui.label("Temperature:");
+                                            ui.add(
+                                                egui::DragValue::new(&mut self.params.temperature)
+                                                    .range(0.0..=1.0)
+                                                    .speed(0.0001),
+                                            );
+                                            ui.end_row();
+
+                                            ui.label("Cooling:");
+                                            egui::ComboBox::from_id_salt("cooling_schedule")
+                                                .selected_text(self.params.cooling_schedule.name())
+                                                .show_ui(ui, |ui| {
+                                                    for schedule in CoolingSchedule::ALL {
+                                                        ui.selectable_value(
+                                                            &mut self.params.cooling_schedule,
+                                                            schedule,
+                                                            schedule.name(),
+                                                        );
+                                                    }
+                                                });
+                                            ui.end_row();
+                                        }
+
+                                        if self.params.optimizer == OptimizerKind::DifferentialEvolution {
+                                            ui.label("Strategy:");
+                                            egui::ComboBox::from_id_salt("de_strategy")
+                                                .selected_text(self.params.de_strategy.name())
+                                                .show_ui(ui, |ui| {
+                                                    for strategy in DeStrategy::ALL {
+                                                        ui.selectable_value(
+                                                            &mut self.params.de_strategy,
+                                                            strategy,
+                                                            strategy.name(),
+                                                        );
+                                                    }
+                                                });
+                                            ui.end_row();
+
+                                            ui.label("Differential Weight:");
+                                            ui.add(
+                                                egui::DragValue::new(&mut self.params.differential_weight)
+                                                    .range(0.0..=2.0)
+                                                    .speed(0.01),
+                                            );
+                                            ui.end_row();
+
+                                            ui.label("Crossover Rate:");
+                                            ui.add(
+                                                egui::DragValue::new(&mut self.params.crossover_rate)
+                                                    .range(0.0..=1.0)
+                                                    .speed(0.01),
+                                            );
+                                            ui.end_row();
+                                        }
+
+                                        ui.label("Islands:");
+                                        ui.add(
+                                            egui::DragValue::new(&mut self.params.islands)
+                                                .range(1..=64)
+                                                .speed(1.0),
+                                        );
+                                        ui.end_row();
+
+                                        if self.params.islands > 1 {
+                                            ui.label("Migrate Every:");
+                                            ui.add(
+                                                egui::DragValue::new(&mut self.params.migration_interval)
+                                                    .speed(1.0)
+                                                    .suffix(" generations"),
+                                            );
+                                            ui.end_row();
+
+                                            ui.label("Migration Size:");
+                                            ui.add(
+                                                egui::DragValue::new(&mut self.params.migration_size)
+                                                    .speed(1.0),
+                                            );
+                                            ui.end_row();
+                                        }
+                                    });
+                            });
+
+                        egui::Grid::new("placement_grid")
+                            .spacing(egui::vec2(8.0, 8.0))
+                            .show(ui, |ui| {
+                                ui.label("Optimal Color:");
+                                ui.checkbox(&mut self.params.optimal_color, "");
+                                ui.end_row();
+
+                                ui.label("Error Guided:");
+                                ui.checkbox(&mut self.params.error_guided, "");
+                                ui.end_row();
+
+                                ui.label("Coarse To Fine:");
+                                ui.checkbox(&mut self.params.coarse_to_fine, "");
+                                ui.end_row();
+
+                                if self.params.coarse_to_fine {
+                                    ui.label("Image Pyramid:");
+                                    ui.checkbox(&mut self.params.pyramid, "");
+                                    ui.end_row();
+                                }
+
+                                ui.label("Saliency:");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.params.saliency)
+                                        .range(0.0..=1.0)
+                                        .speed(0.01),
+                                );
+                                ui.end_row();
+
+                                ui.label("Refine Passes:");
+                                ui.add(egui::DragValue::new(&mut self.params.refine_passes).speed(1.0));
+                                ui.end_row();
+
+                                ui.label("Refine Every:");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.params.refine_interval)
+                                        .speed(1.0)
+                                        .suffix(" shapes"),
+                                );
+                                ui.end_row();
+
+                                if self.params.refine_passes > 0 || self.params.refine_interval > 0 {
+                                    ui.label("Refine Generations:");
+                                    ui.add(
+                                        egui::DragValue::new(&mut self.params.refine_generations)
+                                            .speed(1.0),
+                                    );
+                                    ui.end_row();
+                                }
+
+                                ui.label("Prune Shapes:");
+                                ui.checkbox(&mut self.use_prune_threshold, "");
+                                ui.end_row();
+
+                                if self.use_prune_threshold {
+                                    ui.label("Prune Threshold:");
+                                    ui.add(
+                                        egui::DragValue::new(&mut self.prune_threshold_value)
+                                            .range(0.0..=0.1)
+                                            .speed(0.00001),
+                                    );
+                                    ui.end_row();
+                                }
+                            });
+
+                        egui::CollapsingHeader::new("Stop Conditions")
+                            .default_open(false)
+                            .show(ui, |ui| {
+                                egui::Grid::new("stop_grid")
+                                    .spacing(egui::vec2(8.0, 8.0))
+                                    .show(ui, |ui| {
+                                        ui.label("Target MSE:");
+                                        optional_value(ui, &mut self.params.target_mse, 0.0, 100.0, 1.0);
+                                        ui.end_row();
+
+                                        ui.label("Target PSNR:");
+                                        optional_value(ui, &mut self.params.target_psnr, 0.0, 30.0, 0.1);
+                                        ui.end_row();
+
+                                        ui.label("Stagnation Limit:");
+                                        optional_value(ui, &mut self.params.stagnation_generations, 1, 32, 1.0);
+                                        ui.end_row();
+
+                                        ui.label("Time Budget (s):");
+                                        let mut seconds = self.params.time_budget.map(|d| d.as_secs_f64());
+                                        optional_value(ui, &mut seconds, 0.0, 60.0, 1.0);
+                                        self.params.time_budget = seconds.map(Duration::from_secs_f64);
+                                        ui.end_row();
+                                    });
+                            });
+
+                        egui::CollapsingHeader::new("Constraints")
+                            .default_open(false)
+                            .show(ui, |ui| {
+                                egui::Grid::new("constraints_grid")
+                                    .spacing(egui::vec2(8.0, 8.0))
+                                    .show(ui, |ui| {
+                                        ui.label("Min Alpha:");
+                                        ui.add(
+                                            egui::DragValue::new(&mut self.params.min_alpha)
+                                                .range(0..=self.params.max_alpha)
+                                                .speed(1.0),
+                                        );
+                                        ui.end_row();
+
+                                        ui.label("Max Alpha:");
+                                        ui.add(
+                                            egui::DragValue::new(&mut self.params.max_alpha)
+                                                .range(self.params.min_alpha..=255)
+                                                .speed(1.0),
+                                        );
+                                        ui.end_row();
+
+                                        let constraints = &mut self.params.constraints;
+                                        ui.label("Margin:");
+                                        optional_value(ui, &mut constraints.margin, 0.0, 0.0, 0.001);
+                                        ui.end_row();
+
+                                        ui.label("Min Area:");
+                                        optional_value(ui, &mut constraints.min_area, 0.0, 0.0001, 0.0001);
+                                        ui.end_row();
+
+                                        ui.label("Max Area:");
+                                        optional_value(ui, &mut constraints.max_area, 0.0, 0.25, 0.001);
+                                        ui.end_row();
+
+                                        ui.label("Max Edge:");
+                                        optional_value(ui, &mut constraints.max_edge, 0.0, 0.5, 0.001);
+                                        ui.end_row();
+
+                                        ui.label("Max Aspect Ratio:");
+                                        optional_value(ui, &mut constraints.max_aspect_ratio, 1.0, 4.0, 0.1);
+                                        ui.end_row();
+                                    });
+                            });
+
+                        egui::Grid::new("output_grid")
+                            .spacing(egui::vec2(8.0, 8.0))
+                            .show(ui, |ui| {
                                 ui.label("Use Custom Seed:");
                                 ui.checkbox(&mut self.use_custom_seed, "");
                                 ui.end_row();
@@ -280,6 +733,15 @@ impl eframe::App for TriKlopsApp {
                                     );
                                     ui.end_row();
                                 }
+
+                                ui.label("SVG Precision:");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.params.svg_precision)
+                                        .range(0..=6)
+                                        .speed(0.1)
+                                        .suffix(" decimals"),
+                                );
+                                ui.end_row();
                             });
                     });
 
@@ -295,24 +757,33 @@ impl eframe::App for TriKlopsApp {
                                 self.stop_algorithm();
                             }
                             ui.add_space(8.0);
-                            ui.label(format!(
-                                "Triangle: {}/{}, Fitness: {:.2}",
-                                progress_data.triangle_index + 1,
-                                self.params.num_triangles,
-                                progress_data.current_fitness
-                            ));
+                            if progress_data.is_refining {
+                                ui.label(format!(
+                                    "Refining: {}/{}",
+                                    progress_data.triangle_index + 1,
+                                    self.params.num_triangles
+                                ));
+                            } else {
+                                ui.label(format!(
+                                    "Triangle: {}/{}, Fitness: {}",
+                                    progress_data.triangle_index + 1,
+                                    self.params.num_triangles,
+                                    progress_data.fitness_label()
+                                ));
+                            }
                         });
                     } else {
                         ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
-                            ui.scope(|ui| {
-                                ui.set_enabled(has_reference_image);
+                            ui.add_enabled_ui(has_reference_image, |ui| {
                                 let start_button = egui::Button::new("Start Processing");
-                                if ui.add(start_button).clicked() {
-                                    if has_reference_image {
-                                        self.start_algorithm(ctx);
-                                    }
+                                if ui.add(start_button).clicked() && has_reference_image {
+                                    self.start_algorithm(ctx);
                                 }
                             });
+                            if progress_data.is_complete && self.params.prune_threshold.is_some() {
+                                ui.add_space(8.0);
+                                ui.label(format!("Pruned {} shapes", progress_data.num_pruned));
+                            }
                         });
                     }
                     ui.add_space(8.0);
@@ -326,8 +797,21 @@ impl eframe::App for TriKlopsApp {
                     if let Some(ref img) = self.reference_image {
                         let size = [img.width() as usize, img.height() as usize];
                         let pixels: Vec<egui::Color32> = img
-                            .pixels()
-                            .map(|p| egui::Color32::from_rgb(p.0[0], p.0[1], p.0[2]))
+                            .enumerate_pixels()
+                            .map(|(x, y, p)| {
+                                // Dim regions with low weight so the map is visible
+                                let shade = match self.weight_map {
+                                    Some(ref weights) => {
+                                        0.25 + 0.75 * weights.get_pixel(x, y).0[0] as f32 / 255.0
+                                    }
+                                    None => 1.0,
+                                };
+                                egui::Color32::from_rgb(
+                                    (p.0[0] as f32 * shade) as u8,
+                                    (p.0[1] as f32 * shade) as u8,
+                                    (p.0[2] as f32 * shade) as u8,
+                                )
+                            })
                             .collect();
 
                         let color_image = egui::ColorImage { size, pixels };
@@ -336,7 +820,24 @@ impl eframe::App for TriKlopsApp {
                             color_image,
                             egui::TextureOptions::default(),
                         );
-                        ui.image(&texture);
+                        let response = ui.add(egui::Image::new(&texture).sense(egui::Sense::drag()));
+
+                        // Left-drag marks important regions, right-drag excludes them
+                        if self.paint_weights && !progress_data.is_running {
+                            if let Some(pos) = response.interact_pointer_pos() {
+                                let value = if response.dragged_by(egui::PointerButton::Secondary) {
+                                    0
+                                } else {
+                                    255
+                                };
+                                let rect = response.rect;
+                                let center = egui::pos2(
+                                    (pos.x - rect.min.x) / rect.width() * size[0] as f32,
+                                    (pos.y - rect.min.y) / rect.height() * size[1] as f32,
+                                );
+                                self.paint_weight_map(center, value);
+                            }
+                        }
                     } else {
                         let texture = self.create_black_square_texture(ctx, "reference_black");
                         ui.image(&texture);
@@ -357,3 +858,27 @@ impl eframe::App for TriKlopsApp {
         }
     }
 }
+
+/// Checkbox that switches an optional parameter on, followed by an editor for
+/// its value, no lower than `min`, while it is on.
+fn optional_value<T: egui::emath::Numeric>(
+    ui: &mut egui::Ui,
+    value: &mut Option<T>,
+    min: T,
+    default: T,
+    speed: f64,
+) {
+    ui.horizontal(|ui| {
+        let mut enabled = value.is_some();
+        if ui.checkbox(&mut enabled, "").changed() {
+            *value = enabled.then_some(default);
+        }
+        if let Some(value) = value {
+            ui.add(
+                egui::DragValue::new(value)
+                    .range(min..=T::MAX)
+                    .speed(speed),
+            );
+        }
+    });
+}
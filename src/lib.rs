@@ -0,0 +1,28 @@
+//! Approximates images with semi-transparent shapes using a genetic algorithm.
+//!
+//! The quickest way in is [`render`], which runs to completion on the calling
+//! thread. [`run_algorithm`] is the variant the GUI uses to observe a run while
+//! it is in progress and stop it early.
+
+pub mod algo;
+mod cma_es;
+mod differential_evolution;
+mod error_guide;
+pub mod fitness;
+mod genome;
+pub mod optimizer;
+mod pyramid;
+mod refine;
+mod saliency;
+pub mod shape;
+mod ssim;
+
+pub use algo::{
+    draw_shape_onto_canvas, new_document, render, run_algorithm, AlgorithmParams, EvolutionMode,
+    Progress,
+};
+pub use fitness::FitnessMetric;
+pub use optimizer::{
+    CoolingSchedule, Crossover, DeStrategy, OptimizerKind, Selection, StepSchedule,
+};
+pub use shape::{Circle, Constraints, Ellipse, MutationSteps, Quad, Rect, Shape, ShapeKind, Triangle};
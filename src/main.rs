@@ -1,11 +1,21 @@
-#![windows_subsystem = "windows"]
-mod algo;
+// Windows opens no console for the GUI; the CLI attaches to its parent's.
+#![cfg_attr(feature = "gui", windows_subsystem = "windows")]
+mod cli;
+#[cfg(feature = "gui")]
 mod gui;
 
-use crate::gui::TriKlopsApp;
-use eframe::egui;
-
+#[cfg(feature = "gui")]
 fn main() -> eframe::Result {
+    use crate::gui::TriKlopsApp;
+    use eframe::egui;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        #[cfg(windows)]
+        attach_console();
+        std::process::exit(cli::run(&args));
+    }
+
     let app_name = "Tri-Klops";
     env_logger::init();
     let options = eframe::NativeOptions {
@@ -23,3 +33,27 @@ fn main() -> eframe::Result {
         }),
     )
 }
+
+/// Attaches to the console of the process that started this one, such as a
+/// shell, so that CLI output shows up there.
+#[cfg(all(feature = "gui", windows))]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+
+    // Fails harmlessly if there is no parent console, e.g. when started from
+    // Explorer with arguments.
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(feature = "gui"))]
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(cli::run(&args));
+}
@@ -0,0 +1,550 @@
+use crate::algo::AlgorithmParams;
+use crate::cma_es::CmaEs;
+use crate::differential_evolution::DifferentialEvolution;
+use crate::shape::{Constraints, MutationSteps, Shape};
+use rand::prelude::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+/// Search strategy used to find each shape.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OptimizerKind {
+    /// Truncation selection, crossover and mutation over a population.
+    Genetic,
+    /// Keeps the best shape found so far and tries mutations of it.
+    HillClimbing,
+    /// Like `HillClimbing`, but sometimes accepts a worse shape to escape local
+    /// optima, less and less often as the temperature drops.
+    SimulatedAnnealing,
+    /// Covariance matrix adaptation evolution strategy, which learns the shape of
+    /// the fitness landscape around the best shape and samples along it.
+    CmaEs,
+    /// Differential evolution, which moves shapes along the differences between
+    /// other members of the population.
+    DifferentialEvolution,
+}
+
+impl OptimizerKind {
+    pub const ALL: [OptimizerKind; 5] = [
+        OptimizerKind::Genetic,
+        OptimizerKind::HillClimbing,
+        OptimizerKind::SimulatedAnnealing,
+        OptimizerKind::CmaEs,
+        OptimizerKind::DifferentialEvolution,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OptimizerKind::Genetic => "Genetic Algorithm",
+            OptimizerKind::HillClimbing => "Hill Climbing",
+            OptimizerKind::SimulatedAnnealing => "Simulated Annealing",
+            OptimizerKind::CmaEs => "CMA-ES",
+            OptimizerKind::DifferentialEvolution => "Differential Evolution",
+        }
+    }
+}
+
+/// How the simulated annealing temperature falls over the generations of a shape.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CoolingSchedule {
+    /// Falls in a straight line to zero.
+    Linear,
+    /// Falls by a constant factor each generation, to a thousandth at the end.
+    Exponential,
+}
+
+impl CoolingSchedule {
+    pub const ALL: [CoolingSchedule; 2] = [CoolingSchedule::Linear, CoolingSchedule::Exponential];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoolingSchedule::Linear => "Linear",
+            CoolingSchedule::Exponential => "Exponential",
+        }
+    }
+
+    /// Fraction of the initial temperature left after `progress` of the generations,
+    /// with `progress` in `0.0..=1.0`.
+    fn factor(&self, progress: f64) -> f64 {
+        match self {
+            CoolingSchedule::Linear => 1.0 - progress,
+            CoolingSchedule::Exponential => 0.001f64.powf(progress),
+        }
+    }
+}
+
+/// How differential evolution builds the mutant for each member.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeStrategy {
+    /// DE/rand/1/bin: a random member plus a scaled difference of two others.
+    RandOne,
+    /// DE/best/1/bin: the fittest member plus a scaled difference of two others.
+    /// Converges faster but explores less.
+    BestOne,
+}
+
+impl DeStrategy {
+    pub const ALL: [DeStrategy; 2] = [DeStrategy::RandOne, DeStrategy::BestOne];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeStrategy::RandOne => "DE/rand/1/bin",
+            DeStrategy::BestOne => "DE/best/1/bin",
+        }
+    }
+}
+
+/// How the genetic algorithm picks parents.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Selection {
+    /// Uniformly among the `num_selected` fittest candidates.
+    Truncation,
+    /// The fittest of `tournament_size` candidates drawn at random.
+    Tournament,
+    /// With probability proportional to how much fitter a candidate is than
+    /// the worst one.
+    Roulette,
+    /// With probability proportional to rank, from 1 for the worst candidate.
+    Rank,
+}
+
+impl Selection {
+    pub const ALL: [Selection; 4] = [
+        Selection::Truncation,
+        Selection::Tournament,
+        Selection::Roulette,
+        Selection::Rank,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Selection::Truncation => "Truncation",
+            Selection::Tournament => "Tournament",
+            Selection::Roulette => "Roulette",
+            Selection::Rank => "Rank",
+        }
+    }
+}
+
+/// How the genetic algorithm combines two parents.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Crossover {
+    /// Each vertex, center, size, angle and color channel from either parent.
+    Uniform,
+    /// The same random weighted average of the parents for every coordinate
+    /// and color channel.
+    Arithmetic,
+    /// BLX-0.5: every coordinate and color channel drawn independently from
+    /// the span between the parents, widened by half of it on both sides.
+    Blend,
+}
+
+impl Crossover {
+    pub const ALL: [Crossover; 3] = [Crossover::Uniform, Crossover::Arithmetic, Crossover::Blend];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Crossover::Uniform => "Uniform",
+            Crossover::Arithmetic => "Arithmetic",
+            Crossover::Blend => "Blend",
+        }
+    }
+}
+
+/// How mutation step sizes change over the generations spent on one shape.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StepSchedule {
+    /// Always the configured steps.
+    Fixed,
+    /// Rechenberg's 1/5th success rule: steps grow while more than a fifth of
+    /// mutations improve on their parent and shrink while fewer do.
+    OneFifthRule,
+    /// Steps fall from the configured size to a tenth of it by the last generation.
+    Decay,
+}
+
+impl StepSchedule {
+    pub const ALL: [StepSchedule; 3] = [
+        StepSchedule::Fixed,
+        StepSchedule::OneFifthRule,
+        StepSchedule::Decay,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StepSchedule::Fixed => "Fixed",
+            StepSchedule::OneFifthRule => "1/5th Rule",
+            StepSchedule::Decay => "Decay",
+        }
+    }
+}
+
+/// Current mutation steps of an optimizer under a [`StepSchedule`].
+struct StepControl {
+    steps: MutationSteps,
+    schedule: StepSchedule,
+    num_generations: usize,
+    generation_index: usize,
+    factor: f64,
+}
+
+impl StepControl {
+    fn new(params: &AlgorithmParams, num_generations: usize, run_progress: f64) -> Self {
+        let shrink = 1.0 - params.step_shrink.clamp(0.0, 1.0) * run_progress;
+        Self {
+            steps: params.mutation_steps.scaled(shrink),
+            schedule: params.step_schedule,
+            num_generations,
+            generation_index: 0,
+            factor: 1.0,
+        }
+    }
+
+    fn current(&self) -> MutationSteps {
+        match self.schedule {
+            StepSchedule::Fixed => self.steps,
+            StepSchedule::OneFifthRule => self.steps.scaled(self.factor),
+            StepSchedule::Decay => {
+                let progress = self.generation_index as f64 / self.num_generations.max(1) as f64;
+                self.steps.scaled(0.1f64.powf(progress))
+            }
+        }
+    }
+
+    /// Moves on to the next generation, in which `successes` out of `trials`
+    /// mutants were fitter than the shape they came from.
+    fn record(&mut self, successes: usize, trials: usize) {
+        self.generation_index += 1;
+        if self.schedule == StepSchedule::OneFifthRule && trials > 0 {
+            let ratio = successes as f64 / trials as f64;
+            if ratio > 0.2 {
+                self.factor *= 1.22;
+            } else if ratio < 0.2 {
+                self.factor *= 0.82;
+            }
+            self.factor = self.factor.clamp(1.0 / 64.0, 4.0);
+        }
+    }
+}
+
+/// Searches for one shape to place, a generation of candidates at a time.
+pub(crate) trait Optimizer: Send {
+    /// Takes the candidates of the last generation, starting with the initial
+    /// population, together with their fitness and returns the next generation.
+    fn next_generation(
+        &mut self,
+        candidates: Vec<Shape>,
+        fitness_scores: &[f64],
+        rng: &mut StdRng,
+    ) -> Vec<Shape>;
+}
+
+/// Creates a fresh optimizer of the kind selected in `params` that will run for
+/// `num_generations`. `run_progress` is the fraction of the run already done,
+/// which shrinks mutation steps by `step_shrink`.
+pub(crate) fn new_optimizer(
+    params: &AlgorithmParams,
+    num_generations: usize,
+    run_progress: f64,
+) -> Box<dyn Optimizer> {
+    let image_size = (params.image_size, params.image_size);
+    let alpha_range = (params.min_alpha, params.max_alpha);
+    let steps = StepControl::new(params, num_generations, run_progress);
+    match params.optimizer {
+        OptimizerKind::Genetic => Box::new(Genetic {
+            population_size: params.population_size,
+            num_selected: params.num_selected,
+            selection: params.selection,
+            tournament_size: params.tournament_size,
+            elitism: params.elitism,
+            crossover: params.crossover,
+            mutation_rate: params.mutation_rate,
+            steps,
+            image_size,
+            alpha_range,
+            constraints: params.constraints,
+            parent_fitness: Vec::new(),
+        }),
+        OptimizerKind::HillClimbing | OptimizerKind::SimulatedAnnealing => {
+            let temperature = match params.optimizer {
+                OptimizerKind::SimulatedAnnealing => params.temperature,
+                _ => 0.0,
+            };
+            Box::new(LocalSearch {
+                population_size: params.population_size,
+                num_generations,
+                temperature,
+                cooling_schedule: params.cooling_schedule,
+                steps,
+                image_size,
+                alpha_range,
+                constraints: params.constraints,
+                generation_index: 0,
+                current: None,
+            })
+        }
+        OptimizerKind::CmaEs => Box::new(CmaEs::new(
+            image_size,
+            alpha_range,
+            params.constraints,
+            steps.current().position,
+            params.population_size,
+            params.num_selected,
+        )),
+        OptimizerKind::DifferentialEvolution => Box::new(DifferentialEvolution::new(
+            params.de_strategy,
+            params.differential_weight,
+            params.crossover_rate,
+            steps.current(),
+            image_size,
+            alpha_range,
+            params.constraints,
+        )),
+    }
+}
+
+struct Genetic {
+    population_size: usize,
+    num_selected: usize,
+    selection: Selection,
+    tournament_size: usize,
+    elitism: usize,
+    crossover: Crossover,
+    mutation_rate: f64,
+    steps: StepControl,
+    image_size: (u32, u32),
+    alpha_range: (u8, u8),
+    constraints: Constraints,
+    /// Fitness of the first parent of each candidate in the last generation,
+    /// or `None` for elites carried over unchanged.
+    parent_fitness: Vec<Option<f64>>,
+}
+
+impl Optimizer for Genetic {
+    fn next_generation(
+        &mut self,
+        candidates: Vec<Shape>,
+        fitness_scores: &[f64],
+        rng: &mut StdRng,
+    ) -> Vec<Shape> {
+        if candidates.is_empty() {
+            return Vec::new();
+        }
+
+        let mut successes = 0;
+        let mut trials = 0;
+        for (child, parent) in fitness_scores.iter().zip(&self.parent_fitness) {
+            if let Some(parent) = parent {
+                trials += 1;
+                successes += (child > parent) as usize;
+            }
+        }
+        self.steps.record(successes, trials);
+
+        let mut ranked: Vec<usize> = (0..candidates.len()).collect();
+        ranked.sort_by(|&a, &b| fitness_scores[b].partial_cmp(&fitness_scores[a]).unwrap());
+        let num_elites = self.elitism.min(self.population_size).min(candidates.len());
+        let elites = ranked[..num_elites]
+            .iter()
+            .map(|&i| (candidates[i].clone(), None));
+
+        let selector = Selector::new(
+            self.selection,
+            &ranked,
+            fitness_scores,
+            self.num_selected,
+            self.tournament_size,
+        );
+        let steps = self.steps.current();
+        let seeds: Vec<u64> = (num_elites..self.population_size).map(|_| rng.gen()).collect();
+        let children: Vec<(Shape, Option<f64>)> = seeds
+            .into_par_iter()
+            .map(|seed| {
+                let mut thread_rng = StdRng::seed_from_u64(seed);
+                let first = selector.pick(&mut thread_rng);
+                let second = selector.pick(&mut thread_rng);
+                let (parent1, parent2) = (&candidates[first], &candidates[second]);
+                let child = match self.crossover {
+                    Crossover::Uniform => parent1.crossover(parent2, &mut thread_rng),
+                    Crossover::Arithmetic => {
+                        parent1.arithmetic_crossover(parent2, self.alpha_range, &mut thread_rng)
+                    }
+                    Crossover::Blend => {
+                        parent1.blend_crossover(parent2, self.alpha_range, &mut thread_rng)
+                    }
+                };
+                let child = child.mutate(
+                    self.image_size,
+                    self.mutation_rate,
+                    steps,
+                    self.alpha_range,
+                    &self.constraints,
+                    &mut thread_rng,
+                );
+                // Blending two allowed parents can still give a shape that is not.
+                let child = self.constraints.enforce(child, parent1, self.image_size);
+                (child, Some(fitness_scores[first]))
+            })
+            .collect();
+
+        let (children, parent_fitness) = elites.chain(children).unzip();
+        self.parent_fitness = parent_fitness;
+        children
+    }
+}
+
+/// Hill climbing, or simulated annealing with a positive `temperature`.
+///
+/// Each generation mutates the current shape `population_size` times. The best
+/// mutant replaces the current shape if it is fitter or, while annealing, with
+/// the Metropolis probability `exp(delta / (T * error))`. The temperature is
+/// relative to the current error so that it works the same for every metric.
+struct LocalSearch {
+    population_size: usize,
+    num_generations: usize,
+    temperature: f64,
+    cooling_schedule: CoolingSchedule,
+    steps: StepControl,
+    image_size: (u32, u32),
+    alpha_range: (u8, u8),
+    constraints: Constraints,
+    generation_index: usize,
+    current: Option<(Shape, f64)>,
+}
+
+impl Optimizer for LocalSearch {
+    fn next_generation(
+        &mut self,
+        candidates: Vec<Shape>,
+        fitness_scores: &[f64],
+        rng: &mut StdRng,
+    ) -> Vec<Shape> {
+        let progress = self.generation_index as f64 / self.num_generations.max(1) as f64;
+        let temperature = self.temperature * self.cooling_schedule.factor(progress);
+        self.generation_index += 1;
+
+        if let Some((_, current_fitness)) = &self.current {
+            let successes = fitness_scores.iter().filter(|&f| f > current_fitness).count();
+            self.steps.record(successes, fitness_scores.len());
+        }
+
+        if let Some((shape, &fitness)) = candidates
+            .into_iter()
+            .zip(fitness_scores)
+            .max_by(|(_, f1), (_, f2)| f1.partial_cmp(f2).unwrap())
+        {
+            let accept = match &self.current {
+                None => true,
+                Some((_, current_fitness)) => {
+                    let delta = fitness - current_fitness;
+                    delta >= 0.0
+                        || (temperature > 0.0
+                            && rng.gen::<f64>()
+                                < (delta / (temperature * current_fitness.abs())).exp())
+                }
+            };
+            if accept {
+                self.current = Some((shape, fitness));
+            }
+        }
+
+        let Some((current, _)) = &self.current else {
+            return Vec::new();
+        };
+        let steps = self.steps.current();
+        let seeds: Vec<u64> = (0..self.population_size).map(|_| rng.gen()).collect();
+        seeds
+            .into_par_iter()
+            .map(|seed| {
+                let mut thread_rng = StdRng::seed_from_u64(seed);
+                current.mutate(
+                    self.image_size,
+                    1.0,
+                    steps,
+                    self.alpha_range,
+                    &self.constraints,
+                    &mut thread_rng,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Draws parent indices according to a [`Selection`].
+struct Selector<'a> {
+    selection: Selection,
+    fitness_scores: &'a [f64],
+    /// Candidates that can be picked, fittest first.
+    pool: &'a [usize],
+    /// Running totals of the selection weights of `pool`, for roulette and rank.
+    cumulative: Vec<f64>,
+    tournament_size: usize,
+}
+
+impl<'a> Selector<'a> {
+    fn new(
+        selection: Selection,
+        ranked: &'a [usize],
+        fitness_scores: &'a [f64],
+        num_selected: usize,
+        tournament_size: usize,
+    ) -> Self {
+        let pool = match selection {
+            Selection::Truncation => &ranked[..num_selected.max(1).min(ranked.len())],
+            _ => ranked,
+        };
+        let weights: Vec<f64> = match selection {
+            Selection::Roulette => {
+                // Degenerate shapes score f64::MIN; leave them out of the baseline.
+                let worst = pool
+                    .iter()
+                    .map(|&i| fitness_scores[i])
+                    .filter(|&f| f > f64::MIN)
+                    .fold(f64::MAX, f64::min);
+                pool.iter()
+                    .map(|&i| (fitness_scores[i] - worst).max(0.0))
+                    .collect()
+            }
+            Selection::Rank => (0..pool.len()).map(|r| (pool.len() - r) as f64).collect(),
+            _ => Vec::new(),
+        };
+        let mut total = 0.0;
+        let mut cumulative: Vec<f64> = weights
+            .iter()
+            .map(|w| {
+                total += w;
+                total
+            })
+            .collect();
+        if total == 0.0 && !cumulative.is_empty() {
+            cumulative = (1..=pool.len()).map(|i| i as f64).collect();
+        }
+
+        Self {
+            selection,
+            fitness_scores,
+            pool,
+            cumulative,
+            tournament_size: tournament_size.max(1),
+        }
+    }
+
+    fn pick(&self, rng: &mut impl Rng) -> usize {
+        match self.selection {
+            Selection::Truncation => *self.pool.choose(rng).unwrap(),
+            Selection::Tournament => (0..self.tournament_size)
+                .map(|_| *self.pool.choose(rng).unwrap())
+                .max_by(|&a, &b| self.fitness_scores[a].partial_cmp(&self.fitness_scores[b]).unwrap())
+                .unwrap(),
+            Selection::Roulette | Selection::Rank => {
+                let target = rng.gen_range(0.0..*self.cumulative.last().unwrap());
+                let i = self
+                    .cumulative
+                    .partition_point(|&c| c <= target)
+                    .min(self.pool.len() - 1);
+                self.pool[i]
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::fitness::{ErrorMap, FitnessMetric};
+use crate::ssim::{downsample, downsample_weights};
+use crate::shape::Shape;
+use image::RgbImage;
+
+/// Shapes smaller than this many pixels across at a level would be judged on
+/// too few pixels, so they are measured at a finer one.
+const MIN_SHAPE_SIZE: f64 = 8.0;
+
+/// Levels are never smaller than this many pixels across.
+const MIN_IMAGE_SIZE: u32 = 32;
+
+/// A downscaled copy of the canvas and the reference to measure the fitness of
+/// large shapes on, which is much cheaper and barely less accurate than doing so
+/// at full resolution.
+pub(crate) struct Level {
+    factor: u32,
+    canvas_image: RgbImage,
+    reference_image: RgbImage,
+    weights: Vec<f64>,
+    error_map: ErrorMap,
+}
+
+impl Level {
+    /// Returns the coarsest level on which shapes of at least `min_area`, a
+    /// fraction of the image area, are still measured fairly, or `None` if that
+    /// is full resolution.
+    pub fn for_min_area(
+        min_area: f64,
+        canvas_image: &RgbImage,
+        reference_image: &RgbImage,
+        metric: FitnessMetric,
+        weights: &[f64],
+    ) -> Option<Self> {
+        let (width, height) = reference_image.dimensions();
+        let shape_size = min_area.sqrt() * width.min(height) as f64;
+        let mut shift = 0;
+        while shape_size / (2u32 << shift) as f64 >= MIN_SHAPE_SIZE
+            && width.min(height) >> (shift + 1) >= MIN_IMAGE_SIZE
+        {
+            shift += 1;
+        }
+        if shift == 0 {
+            return None;
+        }
+
+        let canvas_image = downscale(canvas_image, shift);
+        let reference_image = downscale(reference_image, shift);
+        let weights = downsample_weights(weights, width, shift);
+        let error_map = ErrorMap::new(&canvas_image, &reference_image, metric, &weights);
+        Some(Self {
+            factor: 1 << shift,
+            canvas_image,
+            reference_image,
+            weights,
+            error_map,
+        })
+    }
+
+    /// Returns `shape` in the coordinates of this level.
+    pub fn shape(&self, shape: &Shape) -> Shape {
+        shape.scaled(1.0 / self.factor as f64)
+    }
+
+    pub fn canvas_image(&self) -> &RgbImage {
+        &self.canvas_image
+    }
+
+    pub fn reference_image(&self) -> &RgbImage {
+        &self.reference_image
+    }
+
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+
+    pub fn error_map(&self) -> &ErrorMap {
+        &self.error_map
+    }
+}
+
+/// Box-filters `image` down by a factor of `2^shift` in each direction, like
+/// the SSIM pyramid, rounding to whole colors.
+fn downscale(image: &RgbImage, shift: u32) -> RgbImage {
+    let (width, height) = (image.width() >> shift, image.height() >> shift);
+    let pixels = downsample(image, shift);
+    RgbImage::from_fn(width, height, |x, y| {
+        let pixel = pixels[(y * width + x) as usize];
+        image::Rgb(pixel.map(|value| value.round() as u8))
+    })
+}
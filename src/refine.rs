@@ -0,0 +1,325 @@
+use crate::algo::{
+    blend_pixel, draw_shape_onto_canvas, is_degenerate, pixel_box, redrawn_pixels, union_box,
+    AlgorithmParams,
+};
+use crate::fitness::ErrorMap;
+use crate::optimizer::new_optimizer;
+use crate::shape::Shape;
+use image::RgbImage;
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+
+/// The shapes above the one being refined, folded into one affine map per pixel:
+/// a value `v` left at a pixel ends up as `factor * v + offset` on the canvas.
+struct Overlay {
+    width: u32,
+    factor: Vec<f64>,
+    offset: Vec<[f64; 3]>,
+}
+
+impl Overlay {
+    fn new(shapes: &[Shape], (width, height): (u32, u32)) -> Self {
+        let mut overlay = Self {
+            width,
+            factor: vec![1.0; (width * height) as usize],
+            offset: vec![[0.0; 3]; (width * height) as usize],
+        };
+        overlay.fold(shapes, [0, 0], [width, height]);
+        overlay
+    }
+
+    /// Takes out `shape`, the lowest of the shapes folded in, by folding the
+    /// `shapes` above it in again over the pixels it touches.
+    fn remove_lowest(&mut self, shape: &Shape, shapes: &[Shape]) {
+        let size = (self.width, self.factor.len() as u32 / self.width);
+        let (min, max) = pixel_box(shape.bounding_box(), size);
+        for y in min[1]..max[1] {
+            for x in min[0]..max[0] {
+                let i = (y * self.width + x) as usize;
+                self.factor[i] = 1.0;
+                self.offset[i] = [0.0; 3];
+            }
+        }
+        self.fold(shapes, min, max);
+    }
+
+    /// Folds `shapes` in over the pixels from `min` up to, excluding, `max`.
+    fn fold(&mut self, shapes: &[Shape], min: [u32; 2], max: [u32; 2]) {
+        for shape in shapes {
+            let color = shape.color();
+            shape.rasterize_within(min, max, |x, y, coverage| {
+                let alpha = color[3] as f64 / 255.0 * coverage;
+                let i = (y * self.width + x) as usize;
+                self.factor[i] *= 1.0 - alpha;
+                for (offset, c) in self.offset[i].iter_mut().zip(color) {
+                    *offset = *offset * (1.0 - alpha) + alpha * c as f64;
+                }
+            });
+        }
+    }
+
+    fn apply(&self, x: u32, y: u32, rgb: [u8; 3]) -> [u8; 3] {
+        let i = (y * self.width + x) as usize;
+        [0, 1, 2].map(|ch| {
+            (self.factor[i] * rgb[ch] as f64 + self.offset[i][ch])
+                .round()
+                .clamp(0.0, 255.0) as u8
+        })
+    }
+}
+
+/// Revisits every shape of `shapes` from the bottom up and re-optimizes it for
+/// `refine_generations` with the configured optimizer against the complete
+/// stack, keeping its place in the drawing order. A shape is only replaced by
+/// one that lowers the error.
+///
+/// `stop` is polled before each shape and `on_shape` called with its index.
+/// Returns the canvas with all shapes drawn.
+pub(crate) fn refine_shapes(
+    shapes: &mut [Shape],
+    params: &AlgorithmParams,
+    reference_image: &RgbImage,
+    weights: &[f64],
+    rng: &mut StdRng,
+    stop: impl Fn() -> bool,
+    mut on_shape: impl FnMut(usize),
+) -> RgbImage {
+    let image_size = reference_image.dimensions();
+    let alpha_range = (params.min_alpha, params.max_alpha);
+    let degeneracy_threshold = params.degeneracy_threshold.unwrap_or(0.0);
+    let mut below = RgbImage::new(image_size.0, image_size.1);
+    let mut canvas = render_shapes(image_size, shapes);
+    let mut error_map = ErrorMap::new(&canvas, reference_image, params.fitness_metric, weights);
+    let mut overlay = Overlay::new(&shapes[shapes.len().min(1)..], image_size);
+
+    for k in 0..shapes.len() {
+        if stop() {
+            break;
+        }
+        on_shape(k);
+
+        if k > 0 {
+            overlay.remove_lowest(&shapes[k], &shapes[k + 1..]);
+        }
+        let original = shapes[k].clone();
+        let score = |shape: &Shape| {
+            if degeneracy_threshold > 0.0 && is_degenerate(shape, degeneracy_threshold) {
+                return f64::MIN;
+            }
+            let pixels = changed_pixels(&original, Some(shape), &below, &overlay);
+            -error_map.error_with_pixels(&pixels, reference_image)
+        };
+
+        let run_progress = k as f64 / shapes.len() as f64;
+        // Shapes stay within the size they were placed with.
+        let shape_params = AlgorithmParams {
+            constraints: params.constraints_at(k as f64 / params.num_triangles.max(1) as f64),
+            ..params.clone()
+        };
+        let mut optimizer = new_optimizer(&shape_params, params.refine_generations, run_progress);
+        let (steps, constraints) = (params.mutation_steps, &shape_params.constraints);
+        let original_score = score(&original);
+        let mut best = (original.clone(), original_score);
+        let seeds: Vec<u64> = (0..params.population_size).map(|_| rng.gen()).collect();
+        let mut population: Vec<Shape> = seeds
+            .into_par_iter()
+            .map(|seed| {
+                let mut thread_rng = StdRng::seed_from_u64(seed);
+                original.mutate(image_size, 1.0, steps, alpha_range, constraints, &mut thread_rng)
+            })
+            .collect();
+
+        for _ in 0..params.refine_generations {
+            if params.optimal_color {
+                population.par_iter_mut().for_each(|shape| {
+                    let color = optimal_color(shape, &below, &overlay, reference_image, weights);
+                    shape.color_mut()[..3].copy_from_slice(&color);
+                });
+            }
+            let fitness_scores: Vec<f64> = population.par_iter().map(&score).collect();
+            if let Some((shape, &fitness)) = population
+                .iter()
+                .zip(&fitness_scores)
+                .max_by(|(_, f1), (_, f2)| f1.partial_cmp(f2).unwrap())
+            {
+                if fitness > best.1 {
+                    best = (shape.clone(), fitness);
+                }
+            }
+            population = optimizer.next_generation(population, &fitness_scores, rng);
+        }
+
+        if best.1 > original_score {
+            shapes[k] = best.0;
+            let bounding_box = union_box(original.bounding_box(), shapes[k].bounding_box());
+            redraw(
+                &mut canvas,
+                &mut error_map,
+                reference_image,
+                &below,
+                &shapes[k..],
+                pixel_box(bounding_box, image_size),
+            );
+        }
+        draw_shape_onto_canvas(&mut below, &shapes[k]);
+    }
+
+    canvas
+}
+
+/// Removes, from the bottom up, every shape whose removal would raise the error
+/// by no more than `threshold` times the current error. Shapes that are hidden
+/// behind others, or that make the image worse, always go. Returns the number
+/// of shapes removed.
+pub(crate) fn prune_shapes(
+    shapes: &mut Vec<Shape>,
+    params: &AlgorithmParams,
+    reference_image: &RgbImage,
+    weights: &[f64],
+    threshold: f64,
+) -> usize {
+    let image_size = reference_image.dimensions();
+    let mut below = RgbImage::new(image_size.0, image_size.1);
+    let mut canvas = render_shapes(image_size, shapes);
+    let mut error_map = ErrorMap::new(&canvas, reference_image, params.fitness_metric, weights);
+    let mut overlay = Overlay::new(&shapes[shapes.len().min(1)..], image_size);
+    let mut num_removed = 0;
+    let mut k = 0;
+
+    while k < shapes.len() {
+        let shape = &shapes[k];
+
+        // Both errors go through the same approximation of the layers above,
+        // so a shape that changes nothing has a contribution of exactly zero.
+        let error = |replacement| {
+            let pixels = changed_pixels(shape, replacement, &below, &overlay);
+            error_map.error_with_pixels(&pixels, reference_image)
+        };
+        let with_shape = error(Some(shape));
+        let contribution = error(None) - with_shape;
+
+        if contribution <= threshold * with_shape {
+            let removed = shapes.remove(k);
+            redraw(
+                &mut canvas,
+                &mut error_map,
+                reference_image,
+                &below,
+                &shapes[k..],
+                pixel_box(removed.bounding_box(), image_size),
+            );
+            num_removed += 1;
+        } else {
+            draw_shape_onto_canvas(&mut below, shape);
+            k += 1;
+        }
+        if k < shapes.len() {
+            overlay.remove_lowest(&shapes[k], &shapes[k + 1..]);
+        }
+    }
+
+    num_removed
+}
+
+/// Draws `shapes` in order onto a black canvas.
+pub(crate) fn render_shapes((width, height): (u32, u32), shapes: &[Shape]) -> RgbImage {
+    let mut canvas = RgbImage::new(width, height);
+    for shape in shapes {
+        draw_shape_onto_canvas(&mut canvas, shape);
+    }
+    canvas
+}
+
+/// Redraws the pixels of `canvas` from `min` up to, excluding, `max` as
+/// `shapes` drawn over `below`, and updates `error_map` for those that change.
+fn redraw(
+    canvas: &mut RgbImage,
+    error_map: &mut ErrorMap,
+    reference_image: &RgbImage,
+    below: &RgbImage,
+    shapes: &[Shape],
+    pixel_box: ([u32; 2], [u32; 2]),
+) {
+    let pixels = redrawn_pixels(canvas, Some(below), shapes, pixel_box);
+    error_map.update_pixels(&pixels, reference_image);
+    for ([x, y], rgb) in pixels {
+        canvas.put_pixel(x, y, image::Rgb(rgb));
+    }
+}
+
+/// Final colors of the pixels that change when `original`, drawn over `below`
+/// and under `overlay`, is replaced by `replacement` or, without one, removed.
+fn changed_pixels(
+    original: &Shape,
+    replacement: Option<&Shape>,
+    below: &RgbImage,
+    overlay: &Overlay,
+) -> Vec<([u32; 2], [u8; 3])> {
+    let size = below.dimensions();
+    let mut bounding_box = original.bounding_box();
+    if let Some(replacement) = replacement {
+        bounding_box = union_box(bounding_box, replacement.bounding_box());
+    }
+    let ([x0, y0], [x1, y1]) = pixel_box(bounding_box, size);
+    if x0 >= x1 || y0 >= y1 {
+        return Vec::new();
+    }
+
+    let region_width = x1 - x0;
+    let mut region: Vec<Option<[u8; 3]>> = vec![None; (region_width * (y1 - y0)) as usize];
+    let index = |x: u32, y: u32| ((y - y0) * region_width + x - x0) as usize;
+    original.rasterize(size, |x, y, _| region[index(x, y)] = Some(below.get_pixel(x, y).0));
+    if let Some(replacement) = replacement {
+        let color = replacement.color();
+        replacement.rasterize(size, |x, y, coverage| {
+            region[index(x, y)] = Some(blend_pixel(below.get_pixel(x, y).0, color, coverage));
+        });
+    }
+
+    region
+        .iter()
+        .enumerate()
+        .filter_map(|(i, rgb)| {
+            let (x, y) = (x0 + i as u32 % region_width, y0 + i as u32 / region_width);
+            rgb.map(|rgb| ([x, y], overlay.apply(x, y, rgb)))
+        })
+        .collect()
+}
+
+/// Like [`crate::fitness::optimal_color`], but for a shape drawn over `below`
+/// that is then covered by `overlay`.
+fn optimal_color(
+    shape: &Shape,
+    below: &RgbImage,
+    overlay: &Overlay,
+    reference_image: &RgbImage,
+    weights: &[f64],
+) -> [u8; 3] {
+    let color = shape.color();
+    let alpha = color[3] as f64 / 255.0;
+    let width = below.width();
+
+    // Solve sum(w * f * a * (f * (a * c + (1 - a) * b) + o - r)) = 0 for c, where
+    // a is alpha times the coverage of each pixel.
+    let mut numerator = [0.0; 3];
+    let mut denominator = 0.0;
+    shape.rasterize(below.dimensions(), |x, y, coverage| {
+        let alpha = alpha * coverage;
+        let i = (y * width + x) as usize;
+        let (factor, offset) = (overlay.factor[i], overlay.offset[i]);
+        let gain = weights[i] * factor * alpha;
+        let below = below.get_pixel(x, y).0;
+        let reference = reference_image.get_pixel(x, y).0;
+        denominator += gain * factor * alpha;
+        for ch in 0..3 {
+            let rest = factor * (1.0 - alpha) * below[ch] as f64 + offset[ch];
+            numerator[ch] += gain * (reference[ch] as f64 - rest);
+        }
+    });
+
+    if denominator == 0.0 {
+        return [color[0], color[1], color[2]];
+    }
+    numerator.map(|n| (n / denominator).round().clamp(0.0, 255.0) as u8)
+}
@@ -0,0 +1,124 @@
+use crate::shape::{Constraints, Shape, ShapeKind};
+use image::{ImageBuffer, Luma, RgbImage};
+use imageproc::filter::gaussian_blur_f32;
+use imageproc::gradients::sobel_gradients;
+use rand::Rng;
+
+/// Automatic importance map derived from how much detail the reference has.
+///
+/// With a `strength` of 0 every pixel counts the same; at 1 flat regions count
+/// for nothing. The same map steers new shapes toward detailed regions and makes
+/// them smaller there, so flat regions get filled with a few large shapes.
+pub(crate) struct Saliency {
+    width: u32,
+    strength: f64,
+    detail: Vec<f64>,
+    sampler: PositionSampler,
+}
+
+impl Saliency {
+    pub fn new(reference_image: &RgbImage, strength: f64) -> Self {
+        let strength = strength.clamp(0.0, 1.0);
+        let detail = detail_map(reference_image);
+        let weights: Vec<f64> = detail.iter().map(|d| 1.0 - strength + strength * d).collect();
+        Self {
+            width: reference_image.width(),
+            strength,
+            sampler: PositionSampler::new(&weights, reference_image.width()),
+            detail,
+        }
+    }
+
+    /// Fitness weight of the pixel at row-major index `i`.
+    pub fn weight(&self, i: usize) -> f64 {
+        1.0 - self.strength + self.strength * self.detail[i]
+    }
+
+    /// Creates a random shape around a position drawn in proportion to the weights,
+    /// sized down the more detail there is at that position.
+    pub fn random_shape(
+        &self,
+        kind: ShapeKind,
+        image_size: (u32, u32),
+        alpha_range: (u8, u8),
+        constraints: &Constraints,
+        rng: &mut impl Rng,
+    ) -> Shape {
+        let center = self.sampler.sample(rng);
+        let detail = self.detail[(center[1] as u32 * self.width + center[0] as u32) as usize];
+        let max_radius = (image_size.0.min(image_size.1) / 2).max(1) as f64;
+        let radius = max_radius * (1.0 - 0.9 * self.strength * detail);
+        Shape::random_near(kind, center, radius, image_size, alpha_range, constraints, rng)
+    }
+}
+
+/// Draws pixel positions with probability proportional to a per-pixel weight.
+pub(crate) struct PositionSampler {
+    width: u32,
+    cumulative: Vec<f64>,
+}
+
+impl PositionSampler {
+    /// `weights` holds one non-negative weight per pixel of an image `width`
+    /// pixels wide, in row-major order. If they are all zero, positions are
+    /// drawn uniformly.
+    pub fn new(weights: &[f64], width: u32) -> Self {
+        let mut total = 0.0;
+        let mut cumulative: Vec<f64> = weights
+            .iter()
+            .map(|w| {
+                total += w.max(0.0);
+                total
+            })
+            .collect();
+        if total == 0.0 {
+            cumulative = (1..=weights.len()).map(|i| i as f64).collect();
+        }
+        Self { width, cumulative }
+    }
+
+    /// Returns a point drawn uniformly from within the chosen pixel.
+    pub fn sample(&self, rng: &mut impl Rng) -> [f64; 2] {
+        let target = rng.gen_range(0.0..*self.cumulative.last().unwrap());
+        let i = self
+            .cumulative
+            .partition_point(|&c| c <= target)
+            .min(self.cumulative.len() - 1) as u32;
+        [
+            (i % self.width) as f64 + rng.gen::<f64>(),
+            (i / self.width) as f64 + rng.gen::<f64>(),
+        ]
+    }
+}
+
+/// Gaussian blur with a radius proportional to the image size, so maps of
+/// small details come out alike however large the image is.
+pub(crate) fn blur(image: &ImageBuffer<Luma<f32>, Vec<f32>>) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    let (width, height) = image.dimensions();
+    let sigma = (width.min(height) as f32 / 128.0).max(1.0);
+    gaussian_blur_f32(image, sigma)
+}
+
+/// How much detail each pixel of the reference has, in `0.0..=1.0` and row-major
+/// order. This is the Sobel gradient magnitude of the luma, blurred so the
+/// surroundings of an edge count as detailed too, and scaled so that the 95th
+/// percentile maps to 1.
+fn detail_map(reference_image: &RgbImage) -> Vec<f64> {
+    let (width, height) = reference_image.dimensions();
+    let gray = image::imageops::grayscale(reference_image);
+    let gradients = sobel_gradients(&gray);
+    let magnitude: ImageBuffer<Luma<f32>, Vec<f32>> =
+        ImageBuffer::from_fn(width, height, |x, y| {
+            Luma([gradients.get_pixel(x, y).0[0] as f32])
+        });
+    let blurred = blur(&magnitude);
+
+    let mut sorted: Vec<f32> = blurred.pixels().map(|p| p.0[0]).collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let scale = sorted[sorted.len() * 95 / 100].max(f32::EPSILON) as f64;
+
+    blurred
+        .pixels()
+        .map(|p| (p.0[0] as f64 / scale).min(1.0))
+        .collect()
+}
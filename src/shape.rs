@@ -0,0 +1,1171 @@
+use rand::Rng;
+use std::f64::consts::PI;
+use svg::node::element::{Circle as SvgCircle, Ellipse as SvgEllipse, Polygon, Rectangle};
+use svg::Node;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShapeKind {
+    Triangle,
+    Quad,
+    Rect,
+    Ellipse,
+    Circle,
+    Mixed,
+}
+
+impl ShapeKind {
+    pub const ALL: [ShapeKind; 6] = [
+        ShapeKind::Triangle,
+        ShapeKind::Quad,
+        ShapeKind::Rect,
+        ShapeKind::Ellipse,
+        ShapeKind::Circle,
+        ShapeKind::Mixed,
+    ];
+
+    /// Picks a concrete kind at random for `Mixed`, otherwise returns `self`.
+    fn resolve(self, rng: &mut impl Rng) -> ShapeKind {
+        match self {
+            ShapeKind::Mixed => ShapeKind::ALL[rng.gen_range(0..ShapeKind::ALL.len() - 1)],
+            kind => kind,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShapeKind::Triangle => "Triangle",
+            ShapeKind::Quad => "Quad",
+            ShapeKind::Rect => "Rectangle",
+            ShapeKind::Ellipse => "Ellipse",
+            ShapeKind::Circle => "Circle",
+            ShapeKind::Mixed => "Mixed",
+        }
+    }
+}
+
+/// Largest changes [`Shape::mutate`] makes to each property of a shape.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MutationSteps {
+    /// Vertex, center and length jitter, as a fraction of the image size.
+    pub position: f64,
+    /// Rotation jitter in degrees.
+    pub angle: f64,
+    /// Jitter of each color channel and of alpha.
+    pub color: f64,
+}
+
+impl Default for MutationSteps {
+    fn default() -> Self {
+        Self {
+            position: 0.1,
+            angle: 18.0,
+            color: 10.0,
+        }
+    }
+}
+
+impl MutationSteps {
+    /// Returns the steps multiplied by `factor`.
+    pub fn scaled(self, factor: f64) -> Self {
+        Self {
+            position: self.position * factor,
+            angle: self.angle * factor,
+            color: self.color * factor,
+        }
+    }
+}
+
+/// Limits on the geometry of shapes, enforced whenever one is created or
+/// mutated. Lengths are fractions of the larger image dimension and areas
+/// fractions of the image area; `None` leaves a property unbounded.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Constraints {
+    /// How far outside the canvas vertices, and the centers of rectangles,
+    /// ellipses and circles, may lie. Zero keeps them on the canvas.
+    pub margin: Option<f64>,
+    pub min_area: Option<f64>,
+    pub max_area: Option<f64>,
+    /// Longest edge of a polygon, or longest axis of an ellipse or circle.
+    pub max_edge: Option<f64>,
+    /// Largest ratio between the long and short principal axes of a shape.
+    pub max_aspect_ratio: Option<f64>,
+}
+
+impl Constraints {
+    /// Random shapes violating the constraints are redrawn this many times
+    /// before a regular fallback shape is used instead.
+    const MAX_ATTEMPTS: usize = 1000;
+
+    /// Moves vertices and centers of `shape` back within the margin around an
+    /// image of `image_size`.
+    pub fn clamp(&self, shape: &mut Shape, image_size: (u32, u32)) {
+        let Some(margin) = self.margin else {
+            return;
+        };
+        let margin = margin * image_size.0.max(image_size.1) as f64;
+        let clamp = |point: &mut [f64; 2]| {
+            point[0] = point[0].clamp(-margin, image_size.0 as f64 + margin);
+            point[1] = point[1].clamp(-margin, image_size.1 as f64 + margin);
+        };
+        match shape {
+            Shape::Triangle(s) => s.vertices.iter_mut().for_each(clamp),
+            Shape::Quad(s) => s.vertices.iter_mut().for_each(clamp),
+            Shape::Rect(Rect { center, .. })
+            | Shape::Ellipse(Ellipse { center, .. })
+            | Shape::Circle(Circle { center, .. }) => clamp(center),
+        }
+    }
+
+    /// Whether `shape` has an allowed area, edge length and aspect ratio on an
+    /// image of `image_size`. Quads must also stay convex.
+    pub fn allows(&self, shape: &Shape, image_size: (u32, u32)) -> bool {
+        let image_area = image_size.0 as f64 * image_size.1 as f64;
+        let image_length = image_size.0.max(image_size.1) as f64;
+        let area = shape.area() / image_area;
+        if let Shape::Quad(s) = shape {
+            if !is_convex(&s.vertices) {
+                return false;
+            }
+        }
+        !(self.min_area.is_some_and(|min| area < min)
+            || self.max_area.is_some_and(|max| area > max)
+            || self
+                .max_edge
+                .is_some_and(|max| shape.longest_edge() > max * image_length)
+            || self
+                .max_aspect_ratio
+                .is_some_and(|max| shape.aspect_ratio() > max))
+    }
+
+    /// Clamps `shape` and returns it if it is allowed, or `fallback` otherwise.
+    pub fn enforce(&self, mut shape: Shape, fallback: &Shape, image_size: (u32, u32)) -> Shape {
+        self.clamp(&mut shape, image_size);
+        if self.allows(&shape, image_size) {
+            shape
+        } else {
+            fallback.clone()
+        }
+    }
+
+    /// Returns how far from its center a shape can reach within the area and
+    /// edge limits, or `None` if its size is not limited.
+    pub fn max_radius(&self, image_size: (u32, u32)) -> Option<f64> {
+        let image_area = image_size.0 as f64 * image_size.1 as f64;
+        let image_length = image_size.0.max(image_size.1) as f64;
+        let by_area = self.max_area.map(|max| (max * image_area).sqrt());
+        let by_edge = self.max_edge.map(|max| max * image_length / 2.0);
+        match (by_area, by_edge) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Calls `make` until it returns an allowed shape, clamped, or returns `None`
+    /// after [`Self::MAX_ATTEMPTS`].
+    fn retry<R: Rng>(
+        &self,
+        image_size: (u32, u32),
+        rng: &mut R,
+        mut make: impl FnMut(&mut R) -> Shape,
+    ) -> Option<Shape> {
+        (0..Self::MAX_ATTEMPTS).find_map(|_| {
+            let mut shape = make(rng);
+            self.clamp(&mut shape, image_size);
+            self.allows(&shape, image_size).then_some(shape)
+        })
+    }
+
+    /// Returns a regular shape of `kind` in the middle of the image, with an
+    /// area halfway between the limits and shrunk to the edge limit. Its
+    /// aspect ratio is 1, so it is allowed unless the constraints contradict
+    /// each other or the margin cuts it off.
+    fn fallback(
+        &self,
+        kind: ShapeKind,
+        image_size: (u32, u32),
+        alpha_range: (u8, u8),
+        rng: &mut impl Rng,
+    ) -> Shape {
+        let kind = kind.resolve(rng);
+        let color = random_color(alpha_range, rng);
+        let image_area = image_size.0 as f64 * image_size.1 as f64;
+        let image_length = image_size.0.max(image_size.1) as f64;
+        let center = [image_size.0 as f64 / 2.0, image_size.1 as f64 / 2.0];
+
+        let min_area = self.min_area.unwrap_or(0.0);
+        let max_area = self.max_area.unwrap_or(min_area.max(1.0 / 16.0));
+        let area = (min_area + max_area) / 2.0 * image_area;
+        // The longest edge of each regular shape with that area.
+        let longest_edge = match kind {
+            ShapeKind::Triangle => (4.0 * area / 3f64.sqrt()).sqrt(),
+            ShapeKind::Quad | ShapeKind::Rect => area.sqrt(),
+            ShapeKind::Ellipse | ShapeKind::Circle | ShapeKind::Mixed => 2.0 * (area / PI).sqrt(),
+        };
+        let scale = self
+            .max_edge
+            .map_or(1.0, |max| (0.99 * max * image_length / longest_edge).min(1.0));
+        let edge = longest_edge * scale;
+
+        let mut shape = match kind {
+            ShapeKind::Triangle => {
+                let radius = edge / 3f64.sqrt();
+                let mut vertices = [[0.0; 2]; 3];
+                for (i, vertex) in vertices.iter_mut().enumerate() {
+                    let angle = (i as f64 * 120.0 - 90.0).to_radians();
+                    *vertex = [
+                        center[0] + radius * angle.cos(),
+                        center[1] + radius * angle.sin(),
+                    ];
+                }
+                Shape::Triangle(Triangle { vertices, color })
+            }
+            ShapeKind::Quad => {
+                let half = edge / 2.0;
+                let vertices = [[-half, -half], [half, -half], [half, half], [-half, half]]
+                    .map(|[x, y]| [center[0] + x, center[1] + y]);
+                Shape::Quad(Quad { vertices, color })
+            }
+            ShapeKind::Rect => Shape::Rect(Rect {
+                center,
+                size: [edge, edge],
+                angle: 0.0,
+                color,
+            }),
+            ShapeKind::Ellipse => Shape::Ellipse(Ellipse {
+                center,
+                radii: [edge / 2.0; 2],
+                angle: 0.0,
+                color,
+            }),
+            ShapeKind::Circle | ShapeKind::Mixed => Shape::Circle(Circle {
+                center,
+                radius: edge / 2.0,
+                color,
+            }),
+        };
+        self.clamp(&mut shape, image_size);
+        shape
+    }
+}
+
+/// A triangle. Coordinates are in pixels, with the pixel at column `x` and row
+/// `y` covering the unit square from `(x, y)` to `(x + 1, y + 1)`.
+#[derive(Clone)]
+pub struct Triangle {
+    pub vertices: [[f64; 2]; 3],
+    pub color: [u8; 4],
+}
+
+/// A convex quadrilateral. Vertices are kept in winding order.
+#[derive(Clone)]
+pub struct Quad {
+    pub vertices: [[f64; 2]; 4],
+    pub color: [u8; 4],
+}
+
+/// A rectangle of `size` centered on `center`, rotated by `angle` degrees.
+#[derive(Clone)]
+pub struct Rect {
+    pub center: [f64; 2],
+    pub size: [f64; 2],
+    pub angle: f64,
+    pub color: [u8; 4],
+}
+
+/// An ellipse with semi-axes `radii` centered on `center`, rotated by `angle` degrees.
+#[derive(Clone)]
+pub struct Ellipse {
+    pub center: [f64; 2],
+    pub radii: [f64; 2],
+    pub angle: f64,
+    pub color: [u8; 4],
+}
+
+#[derive(Clone)]
+pub struct Circle {
+    pub center: [f64; 2],
+    pub radius: f64,
+    pub color: [u8; 4],
+}
+
+#[derive(Clone)]
+pub enum Shape {
+    Triangle(Triangle),
+    Quad(Quad),
+    Rect(Rect),
+    Ellipse(Ellipse),
+    Circle(Circle),
+}
+
+impl Shape {
+    /// Creates a shape with a random color placed uniformly over the image. If
+    /// `constraints` limit its size, it is kept around a random center instead.
+    /// Should no random shape be allowed, a regular one in the middle of the
+    /// image is returned.
+    pub fn random(
+        kind: ShapeKind,
+        image_size: (u32, u32),
+        alpha_range: (u8, u8),
+        constraints: &Constraints,
+        rng: &mut impl Rng,
+    ) -> Shape {
+        let (width, height) = (image_size.0 as f64, image_size.1 as f64);
+        let max_radius = (width.min(height) / 2.0).floor().max(1.0);
+        let limit = constraints.max_radius(image_size);
+        constraints
+            .retry(image_size, rng, |rng| {
+                let kind = kind.resolve(rng);
+                if kind == ShapeKind::Triangle && limit.is_none() {
+                    let color = random_color(alpha_range, rng);
+                    let mut vertices = [[0.0; 2]; 3];
+                    for vertex in vertices.iter_mut() {
+                        *vertex = [rng.gen_range(0.0..width), rng.gen_range(0.0..height)];
+                    }
+                    return Shape::Triangle(Triangle { vertices, color });
+                }
+
+                let center = [rng.gen_range(0.0..width), rng.gen_range(0.0..height)];
+                let radius = limit.map_or(max_radius, |limit| max_radius.min(limit));
+                Shape::unconstrained_near(kind, center, radius, alpha_range, rng)
+            })
+            .unwrap_or_else(|| constraints.fallback(kind, image_size, alpha_range, rng))
+    }
+
+    /// Creates a shape with a random color that lies around `center` and extends
+    /// at most about `radius` pixels from it, or less if `constraints` limit its
+    /// size. Should no random shape be allowed, a regular one in the middle of
+    /// the image is returned.
+    pub fn random_near(
+        kind: ShapeKind,
+        center: [f64; 2],
+        radius: f64,
+        image_size: (u32, u32),
+        alpha_range: (u8, u8),
+        constraints: &Constraints,
+        rng: &mut impl Rng,
+    ) -> Shape {
+        let radius = constraints
+            .max_radius(image_size)
+            .map_or(radius, |limit| radius.min(limit));
+        constraints
+            .retry(image_size, rng, |rng| {
+                Shape::unconstrained_near(kind, center, radius, alpha_range, rng)
+            })
+            .unwrap_or_else(|| constraints.fallback(kind, image_size, alpha_range, rng))
+    }
+
+    fn unconstrained_near(
+        kind: ShapeKind,
+        center: [f64; 2],
+        radius: f64,
+        alpha_range: (u8, u8),
+        rng: &mut impl Rng,
+    ) -> Shape {
+        let kind = kind.resolve(rng);
+        let color = random_color(alpha_range, rng);
+        let radius = radius.max(1.0);
+
+        match kind {
+            ShapeKind::Triangle => {
+                let mut vertices = [[0.0; 2]; 3];
+                for vertex in vertices.iter_mut() {
+                    *vertex = [
+                        center[0] + rng.gen_range(-radius..=radius),
+                        center[1] + rng.gen_range(-radius..=radius),
+                    ];
+                }
+                Shape::Triangle(Triangle { vertices, color })
+            }
+            ShapeKind::Quad => loop {
+                // Points taken in angular order around an ellipse always form a convex
+                // polygon; two of them can still coincide, so retry until they don't.
+                let radii = [rng.gen_range(1.0..=radius), rng.gen_range(1.0..=radius)];
+                let mut angles: Vec<f64> = (0..4).map(|_| rng.gen_range(0.0..2.0 * PI)).collect();
+                angles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let mut vertices = [[0.0; 2]; 4];
+                for (vertex, angle) in vertices.iter_mut().zip(angles) {
+                    *vertex = [
+                        center[0] + radii[0] * angle.cos(),
+                        center[1] + radii[1] * angle.sin(),
+                    ];
+                }
+                if is_convex(&vertices) {
+                    break Shape::Quad(Quad { vertices, color });
+                }
+            },
+            ShapeKind::Rect => Shape::Rect(Rect {
+                center,
+                size: [
+                    rng.gen_range(1.0..=2.0 * radius),
+                    rng.gen_range(1.0..=2.0 * radius),
+                ],
+                angle: rng.gen_range(0.0..180.0),
+                color,
+            }),
+            ShapeKind::Ellipse => Shape::Ellipse(Ellipse {
+                center,
+                radii: [rng.gen_range(1.0..=radius), rng.gen_range(1.0..=radius)],
+                angle: rng.gen_range(0.0..180.0),
+                color,
+            }),
+            ShapeKind::Circle | ShapeKind::Mixed => Shape::Circle(Circle {
+                center,
+                radius: rng.gen_range(1.0..=radius),
+                color,
+            }),
+        }
+    }
+
+    pub fn color(&self) -> [u8; 4] {
+        match self {
+            Shape::Triangle(s) => s.color,
+            Shape::Quad(s) => s.color,
+            Shape::Rect(s) => s.color,
+            Shape::Ellipse(s) => s.color,
+            Shape::Circle(s) => s.color,
+        }
+    }
+
+    pub fn color_mut(&mut self) -> &mut [u8; 4] {
+        match self {
+            Shape::Triangle(s) => &mut s.color,
+            Shape::Quad(s) => &mut s.color,
+            Shape::Rect(s) => &mut s.color,
+            Shape::Ellipse(s) => &mut s.color,
+            Shape::Circle(s) => &mut s.color,
+        }
+    }
+
+    /// Returns the outline of polygonal shapes, or `None` for curved ones.
+    pub fn polygon(&self) -> Option<Vec<[f64; 2]>> {
+        match self {
+            Shape::Triangle(s) => Some(s.vertices.to_vec()),
+            Shape::Quad(s) => Some(s.vertices.to_vec()),
+            Shape::Rect(s) => {
+                let (sin, cos) = s.angle.to_radians().sin_cos();
+                let half = [s.size[0] / 2.0, s.size[1] / 2.0];
+                let corners = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+                Some(
+                    corners
+                        .iter()
+                        .map(|c| {
+                            let dx = c[0] * half[0];
+                            let dy = c[1] * half[1];
+                            [
+                                s.center[0] + dx * cos - dy * sin,
+                                s.center[1] + dx * sin + dy * cos,
+                            ]
+                        })
+                        .collect(),
+                )
+            }
+            Shape::Ellipse(_) | Shape::Circle(_) => None,
+        }
+    }
+
+    /// Returns the axis-aligned bounding box as `(min, max)` corners.
+    pub fn bounding_box(&self) -> ([f64; 2], [f64; 2]) {
+        if let Some(points) = self.polygon() {
+            let mut min = [f64::MAX; 2];
+            let mut max = [f64::MIN; 2];
+            for p in &points {
+                for i in 0..2 {
+                    min[i] = min[i].min(p[i]);
+                    max[i] = max[i].max(p[i]);
+                }
+            }
+            return (min, max);
+        }
+
+        let (center, half) = match self {
+            Shape::Ellipse(s) => {
+                let (sin, cos) = s.angle.to_radians().sin_cos();
+                let [rx, ry] = s.radii;
+                (
+                    s.center,
+                    [
+                        ((rx * cos).powi(2) + (ry * sin).powi(2)).sqrt(),
+                        ((rx * sin).powi(2) + (ry * cos).powi(2)).sqrt(),
+                    ],
+                )
+            }
+            Shape::Circle(s) => (s.center, [s.radius; 2]),
+            _ => unreachable!(),
+        };
+        (
+            [center[0] - half[0], center[1] - half[1]],
+            [center[0] + half[0], center[1] + half[1]],
+        )
+    }
+
+    /// Returns a copy with all coordinates and lengths multiplied by `factor`.
+    pub fn scaled(&self, factor: f64) -> Shape {
+        let scale = |point: [f64; 2]| [point[0] * factor, point[1] * factor];
+        match self {
+            Shape::Triangle(s) => Shape::Triangle(Triangle {
+                vertices: s.vertices.map(scale),
+                ..*s
+            }),
+            Shape::Quad(s) => Shape::Quad(Quad {
+                vertices: s.vertices.map(scale),
+                ..*s
+            }),
+            Shape::Rect(s) => Shape::Rect(Rect {
+                center: scale(s.center),
+                size: scale(s.size),
+                ..*s
+            }),
+            Shape::Ellipse(s) => Shape::Ellipse(Ellipse {
+                center: scale(s.center),
+                radii: scale(s.radii),
+                ..*s
+            }),
+            Shape::Circle(s) => Shape::Circle(Circle {
+                center: scale(s.center),
+                radius: s.radius * factor,
+                ..*s
+            }),
+        }
+    }
+
+    /// Returns the area in square pixels.
+    pub fn area(&self) -> f64 {
+        match self {
+            Shape::Ellipse(s) => PI * s.radii[0] * s.radii[1],
+            Shape::Circle(s) => PI * s.radius * s.radius,
+            _ => polygon_moments(&self.polygon().unwrap())[0],
+        }
+    }
+
+    /// Returns the length of the longest edge of a polygon, or of the longest
+    /// axis of an ellipse or circle.
+    pub fn longest_edge(&self) -> f64 {
+        match self {
+            Shape::Ellipse(s) => 2.0 * s.radii[0].max(s.radii[1]),
+            Shape::Circle(s) => 2.0 * s.radius,
+            _ => {
+                let points = self.polygon().unwrap();
+                (0..points.len())
+                    .map(|i| {
+                        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                        (b[0] - a[0]).hypot(b[1] - a[1])
+                    })
+                    .fold(0.0, f64::max)
+            }
+        }
+    }
+
+    /// Returns the ratio between the long and short principal axes, that is the
+    /// square root of the ratio of the eigenvalues of the second moments of the
+    /// area. It is 1 for circles and equilateral triangles and the ratio of the
+    /// sides or radii for rectangles and ellipses.
+    pub fn aspect_ratio(&self) -> f64 {
+        match self {
+            Shape::Ellipse(s) => s.radii[0].max(s.radii[1]) / s.radii[0].min(s.radii[1]),
+            Shape::Circle(_) => 1.0,
+            Shape::Rect(s) => s.size[0].max(s.size[1]) / s.size[0].min(s.size[1]),
+            _ => {
+                let [area, xx, yy, xy] = polygon_moments(&self.polygon().unwrap());
+                if area <= 0.0 {
+                    return f64::INFINITY;
+                }
+                let mean = (xx + yy) / 2.0;
+                let spread = (((xx - yy) / 2.0).powi(2) + xy * xy).sqrt();
+                if mean - spread <= 0.0 {
+                    return f64::INFINITY;
+                }
+                ((mean + spread) / (mean - spread)).sqrt()
+            }
+        }
+    }
+
+    /// Calls `f` with the coordinates of every pixel of a `width` by `height`
+    /// image the shape overlaps, along with the fraction of the pixel it covers.
+    /// That is the exact area for polygons and close to it for curved shapes,
+    /// which is how SVG renderers anti-alias.
+    pub fn rasterize(&self, (width, height): (u32, u32), f: impl FnMut(u32, u32, f64)) {
+        self.rasterize_within([0, 0], [width, height], f);
+    }
+
+    /// Like [`Shape::rasterize`], but only visits the pixels from `min` up to,
+    /// excluding, `max`.
+    pub fn rasterize_within(&self, min: [u32; 2], max: [u32; 2], mut f: impl FnMut(u32, u32, f64)) {
+        let (lower, upper) = self.bounding_box();
+        let x0 = lower[0].floor().max(min[0] as f64);
+        let y0 = lower[1].floor().max(min[1] as f64);
+        let x1 = upper[0].ceil().min(max[0] as f64);
+        let y1 = upper[1].ceil().min(max[1] as f64);
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+
+        let mut coverage = Coverage::new([x0, y0], [(x1 - x0) as usize, (y1 - y0) as usize]);
+        let outline = self.outline();
+        for (i, &start) in outline.iter().enumerate() {
+            coverage.add_edge(start, outline[(i + 1) % outline.len()]);
+        }
+        coverage.for_each(|x, y, covered| f(x0 as u32 + x, y0 as u32 + y, covered));
+    }
+
+    /// Returns the outline of the shape as a polygon, with curves flattened
+    /// into segments that stray less than [`FLATTENING_TOLERANCE`] from them.
+    fn outline(&self) -> Vec<[f64; 2]> {
+        if let Some(points) = self.polygon() {
+            return points;
+        }
+        let (center, radii, angle) = match self {
+            Shape::Ellipse(s) => (s.center, s.radii, s.angle),
+            Shape::Circle(s) => (s.center, [s.radius; 2], 0.0),
+            _ => unreachable!(),
+        };
+        let radius = radii[0].max(radii[1]);
+        let step = 2.0 * (1.0 - FLATTENING_TOLERANCE / radius).max(-1.0).acos();
+        let segments = ((2.0 * PI / step).ceil() as usize).clamp(8, 1024);
+        let (sin, cos) = angle.to_radians().sin_cos();
+        (0..segments)
+            .map(|i| {
+                let t = 2.0 * PI * i as f64 / segments as f64;
+                let (dx, dy) = (radii[0] * t.cos(), radii[1] * t.sin());
+                [center[0] + dx * cos - dy * sin, center[1] + dx * sin + dy * cos]
+            })
+            .collect()
+    }
+
+    /// With probability `mutation_rate`, returns a copy with its geometry and
+    /// color jittered by up to `steps`; otherwise an unchanged copy. A mutant
+    /// that breaks `constraints` even after clamping is discarded for a copy.
+    pub fn mutate(
+        &self,
+        image_size: (u32, u32),
+        mutation_rate: f64,
+        steps: MutationSteps,
+        alpha_range: (u8, u8),
+        constraints: &Constraints,
+        rng: &mut impl Rng,
+    ) -> Shape {
+        let mut shape = self.clone();
+        if rng.gen::<f64>() >= mutation_rate {
+            return shape;
+        }
+
+        let x_range = image_size.0 as f64 * steps.position;
+        let y_range = image_size.1 as f64 * steps.position;
+        let r_range = x_range.max(y_range);
+
+        match &mut shape {
+            Shape::Triangle(s) => jitter_vertices(&mut s.vertices, x_range, y_range, rng),
+            Shape::Quad(s) => {
+                let mut vertices = s.vertices;
+                jitter_vertices(&mut vertices, x_range, y_range, rng);
+                if is_convex(&vertices) {
+                    s.vertices = vertices;
+                }
+            }
+            Shape::Rect(s) => {
+                jitter_vertices(std::slice::from_mut(&mut s.center), x_range, y_range, rng);
+                jitter_lengths(&mut s.size, r_range, rng);
+                jitter_angle(&mut s.angle, steps.angle, rng);
+            }
+            Shape::Ellipse(s) => {
+                jitter_vertices(std::slice::from_mut(&mut s.center), x_range, y_range, rng);
+                jitter_lengths(&mut s.radii, r_range, rng);
+                jitter_angle(&mut s.angle, steps.angle, rng);
+            }
+            Shape::Circle(s) => {
+                jitter_vertices(std::slice::from_mut(&mut s.center), x_range, y_range, rng);
+                jitter_lengths(std::slice::from_mut(&mut s.radius), r_range, rng);
+            }
+        }
+
+        let c_range = (steps.color.round() as i32).max(1);
+        let color = shape.color_mut();
+        for component in color.iter_mut().take(3) {
+            if rng.gen::<f64>() < 0.5 {
+                *component = (*component as i32 + rng.gen_range(-c_range..=c_range)).clamp(0, 255) as u8;
+            }
+        }
+        if rng.gen::<f64>() < 0.5 {
+            let alpha = color[3] as i32 + rng.gen_range(-c_range..=c_range);
+            color[3] =
+                alpha.clamp(alpha_range.0 as i32, alpha_range.1.max(alpha_range.0) as i32) as u8;
+        }
+
+        constraints.enforce(shape, self, image_size)
+    }
+
+    /// Combines two parents component by component. Parents of different kinds
+    /// cannot be mixed, so one of them is passed on unchanged.
+    pub fn crossover(&self, other: &Shape, rng: &mut impl Rng) -> Shape {
+        let mut child = match (self, other) {
+            (Shape::Triangle(a), Shape::Triangle(b)) => Shape::Triangle(Triangle {
+                vertices: pick(&a.vertices, &b.vertices, rng),
+                color: a.color,
+            }),
+            (Shape::Quad(a), Shape::Quad(b)) => {
+                let vertices = pick(&a.vertices, &b.vertices, rng);
+                Shape::Quad(Quad {
+                    vertices: if is_convex(&vertices) { vertices } else { a.vertices },
+                    color: a.color,
+                })
+            }
+            (Shape::Rect(a), Shape::Rect(b)) => Shape::Rect(Rect {
+                center: pick_one(a.center, b.center, rng),
+                size: pick_one(a.size, b.size, rng),
+                angle: pick_one(a.angle, b.angle, rng),
+                color: a.color,
+            }),
+            (Shape::Ellipse(a), Shape::Ellipse(b)) => Shape::Ellipse(Ellipse {
+                center: pick_one(a.center, b.center, rng),
+                radii: pick_one(a.radii, b.radii, rng),
+                angle: pick_one(a.angle, b.angle, rng),
+                color: a.color,
+            }),
+            (Shape::Circle(a), Shape::Circle(b)) => Shape::Circle(Circle {
+                center: pick_one(a.center, b.center, rng),
+                radius: pick_one(a.radius, b.radius, rng),
+                color: a.color,
+            }),
+            _ => {
+                return if rng.gen::<f64>() < 0.5 {
+                    self.clone()
+                } else {
+                    other.clone()
+                }
+            }
+        };
+        *child.color_mut() = pick(&self.color(), &other.color(), rng);
+        child
+    }
+
+    /// Averages two parents of the same kind, using a single random weight for
+    /// all coordinates and color channels. Parents of different kinds cannot be
+    /// mixed, so one of them is passed on unchanged.
+    pub fn arithmetic_crossover(&self, other: &Shape, alpha_range: (u8, u8), rng: &mut impl Rng) -> Shape {
+        let weight = rng.gen::<f64>();
+        self.mix_genes(other, alpha_range, rng, |a, b, _| a + weight * (b - a))
+    }
+
+    /// BLX-0.5 crossover: every coordinate and color channel is drawn uniformly
+    /// from the range between the parents' values, extended by half its width on
+    /// either side. Parents of different kinds are handled as in [`Shape::crossover`].
+    pub fn blend_crossover(&self, other: &Shape, alpha_range: (u8, u8), rng: &mut impl Rng) -> Shape {
+        self.mix_genes(other, alpha_range, rng, |a, b, rng| {
+            a + rng.gen_range(-0.5..=1.5) * (b - a)
+        })
+    }
+
+    fn mix_genes<R: Rng>(
+        &self,
+        other: &Shape,
+        alpha_range: (u8, u8),
+        rng: &mut R,
+        mut mix: impl FnMut(f64, f64, &mut R) -> f64,
+    ) -> Shape {
+        if std::mem::discriminant(self) != std::mem::discriminant(other) {
+            return if rng.gen::<f64>() < 0.5 {
+                self.clone()
+            } else {
+                other.clone()
+            };
+        }
+        let genes: Vec<f64> = self
+            .genes(1.0)
+            .into_iter()
+            .zip(other.genes(1.0))
+            .map(|(a, b)| mix(a, b, rng))
+            .collect();
+        self.with_genes(&genes, 1.0, alpha_range)
+    }
+
+    /// Encodes the shape as a vector of real numbers for continuous optimizers.
+    /// Positions and lengths are divided by `scale`, usually the image size,
+    /// angles by 180 degrees and color channels by 255, so that every gene
+    /// varies over a range of about one.
+    pub fn genes(&self, scale: f64) -> Vec<f64> {
+        let mut genes = Vec::new();
+        let mut push = |values: &[f64]| genes.extend(values.iter().map(|v| v / scale));
+        match self {
+            Shape::Triangle(s) => s.vertices.iter().for_each(|v| push(v)),
+            Shape::Quad(s) => s.vertices.iter().for_each(|v| push(v)),
+            Shape::Rect(s) => {
+                push(&s.center);
+                push(&s.size);
+            }
+            Shape::Ellipse(s) => {
+                push(&s.center);
+                push(&s.radii);
+            }
+            Shape::Circle(s) => {
+                push(&s.center);
+                push(&[s.radius]);
+            }
+        }
+        match self {
+            Shape::Rect(Rect { angle, .. }) | Shape::Ellipse(Ellipse { angle, .. }) => {
+                genes.push(angle / 180.0)
+            }
+            _ => {}
+        }
+        genes.extend(self.color().iter().map(|&c| c as f64 / 255.0));
+        genes
+    }
+
+    /// Decodes `genes` laid out as by [`Shape::genes`] into a shape of the same
+    /// kind as `self`. Values are clamped into range and colors rounded; a quad
+    /// whose vertices would not be convex keeps those of `self`.
+    pub fn with_genes(&self, genes: &[f64], scale: f64, alpha_range: (u8, u8)) -> Shape {
+        let mut genes = genes.iter();
+        let mut next = || genes.next().unwrap() * scale;
+        let mut shape = match self {
+            Shape::Triangle(_) => Shape::Triangle(Triangle {
+                vertices: [[next(), next()], [next(), next()], [next(), next()]],
+                color: self.color(),
+            }),
+            Shape::Quad(s) => {
+                let vertices = [[next(), next()], [next(), next()], [next(), next()], [next(), next()]];
+                Shape::Quad(Quad {
+                    vertices: if is_convex(&vertices) { vertices } else { s.vertices },
+                    color: s.color,
+                })
+            }
+            Shape::Rect(s) => Shape::Rect(Rect {
+                center: [next(), next()],
+                size: [next().max(1.0), next().max(1.0)],
+                angle: s.angle,
+                color: s.color,
+            }),
+            Shape::Ellipse(s) => Shape::Ellipse(Ellipse {
+                center: [next(), next()],
+                radii: [next().max(1.0), next().max(1.0)],
+                angle: s.angle,
+                color: s.color,
+            }),
+            Shape::Circle(s) => Shape::Circle(Circle {
+                center: [next(), next()],
+                radius: next().max(1.0),
+                color: s.color,
+            }),
+        };
+        match &mut shape {
+            Shape::Rect(Rect { angle, .. }) | Shape::Ellipse(Ellipse { angle, .. }) => {
+                *angle = (genes.next().unwrap() * 180.0).rem_euclid(180.0)
+            }
+            _ => {}
+        }
+        let color = shape.color_mut();
+        for component in color.iter_mut() {
+            *component = (genes.next().unwrap() * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        color[3] = color[3].clamp(alpha_range.0, alpha_range.1.max(alpha_range.0));
+        shape
+    }
+
+    /// Returns the SVG element for the shape, with coordinates written to at
+    /// most `precision` decimal places.
+    pub fn svg_node(&self, precision: usize) -> Box<dyn Node> {
+        let color = self.color();
+        let fill = format!("rgb({},{},{})", color[0], color[1], color[2]);
+        let opacity = format!("{:.3}", color[3] as f64 / 255.0);
+        let number = |value: f64| format_number(value, precision);
+
+        match self {
+            Shape::Triangle(s) => Box::new(svg_polygon(&s.vertices, precision, fill, opacity)),
+            Shape::Quad(s) => Box::new(svg_polygon(&s.vertices, precision, fill, opacity)),
+            Shape::Rect(s) => Box::new(
+                Rectangle::new()
+                    .set("x", number(s.center[0] - s.size[0] / 2.0))
+                    .set("y", number(s.center[1] - s.size[1] / 2.0))
+                    .set("width", number(s.size[0]))
+                    .set("height", number(s.size[1]))
+                    .set("transform", svg_rotation(s.angle, s.center, precision))
+                    .set("fill", fill)
+                    .set("fill-opacity", opacity),
+            ),
+            Shape::Ellipse(s) => Box::new(
+                SvgEllipse::new()
+                    .set("cx", number(s.center[0]))
+                    .set("cy", number(s.center[1]))
+                    .set("rx", number(s.radii[0]))
+                    .set("ry", number(s.radii[1]))
+                    .set("transform", svg_rotation(s.angle, s.center, precision))
+                    .set("fill", fill)
+                    .set("fill-opacity", opacity),
+            ),
+            Shape::Circle(s) => Box::new(
+                SvgCircle::new()
+                    .set("cx", number(s.center[0]))
+                    .set("cy", number(s.center[1]))
+                    .set("r", number(s.radius))
+                    .set("fill", fill)
+                    .set("fill-opacity", opacity),
+            ),
+        }
+    }
+}
+
+fn random_color(alpha_range: (u8, u8), rng: &mut impl Rng) -> [u8; 4] {
+    [
+        rng.gen(),
+        rng.gen(),
+        rng.gen(),
+        rng.gen_range(alpha_range.0..=alpha_range.1.max(alpha_range.0)),
+    ]
+}
+
+/// How far, in pixels, the flattened outline of a curved shape may stray from
+/// the curve.
+const FLATTENING_TOLERANCE: f64 = 1.0 / 64.0;
+
+/// Accumulates the area of a polygon over a block of pixels, edge by edge, in
+/// the manner of font rasterizers: each edge adds the signed area between it
+/// and the right border of the block, row by row, as differences from the
+/// pixel to its left. Summing a row from the left then gives the coverage.
+struct Coverage {
+    origin: [f64; 2],
+    size: [usize; 2],
+    /// `size[0] + 2` entries per row, so that an edge on the right border of
+    /// the block can spill over.
+    deltas: Vec<f64>,
+}
+
+impl Coverage {
+    fn new(origin: [f64; 2], size: [usize; 2]) -> Self {
+        Self {
+            origin,
+            size,
+            deltas: vec![0.0; (size[0] + 2) * size[1]],
+        }
+    }
+
+    fn add_edge(&mut self, start: [f64; 2], end: [f64; 2]) {
+        let width = self.size[0] as f64;
+        let start = [start[0] - self.origin[0], start[1] - self.origin[1]];
+        let end = [end[0] - self.origin[0], end[1] - self.origin[1]];
+
+        // Outside the block, an edge covers whole rows either entirely or not at
+        // all, just as if it ran along the border, so it is split where it
+        // crosses the left and right borders and the outer parts are moved onto
+        // them.
+        let mut cuts = [0.0, 1.0, 1.0, 1.0];
+        for (cut, border) in cuts[1..3].iter_mut().zip([0.0, width]) {
+            let t = (border - start[0]) / (end[0] - start[0]);
+            if t > 0.0 && t < 1.0 {
+                *cut = t;
+            }
+        }
+        cuts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let point = |t: f64| {
+            let x = start[0] + t * (end[0] - start[0]);
+            let y = start[1] + t * (end[1] - start[1]);
+            [x.clamp(0.0, width), y]
+        };
+        for pair in cuts.windows(2) {
+            if pair[0] < pair[1] {
+                self.add_line(point(pair[0]), point(pair[1]));
+            }
+        }
+    }
+
+    /// Adds a line that lies within the left and right borders.
+    fn add_line(&mut self, start: [f64; 2], end: [f64; 2]) {
+        if start[1] == end[1] {
+            return;
+        }
+        let (direction, top, bottom) = if start[1] < end[1] {
+            (1.0, start, end)
+        } else {
+            (-1.0, end, start)
+        };
+        let y_start = top[1].max(0.0);
+        let y_end = bottom[1].min(self.size[1] as f64);
+        if y_start >= y_end {
+            return;
+        }
+
+        let (width, stride) = (self.size[0] as f64, self.size[0] + 2);
+        let dxdy = (bottom[0] - top[0]) / (bottom[1] - top[1]);
+        let mut x = top[0] + (y_start - top[1]) * dxdy;
+        for row in y_start.floor() as usize..y_end.ceil() as usize {
+            let dy = ((row + 1) as f64).min(y_end) - (row as f64).max(y_start);
+            let x_next = x + dxdy * dy;
+            let d = dy * direction;
+            let line = &mut self.deltas[row * stride..(row + 1) * stride];
+
+            let x0 = x.min(x_next).clamp(0.0, width);
+            let x1 = x.max(x_next).clamp(0.0, width);
+            let (x0_floor, x1_ceil) = (x0.floor(), x1.ceil());
+            let (i0, i1) = (x0_floor as usize, x1_ceil as usize);
+            if i1 <= i0 + 1 {
+                // Within one pixel, the area right of the line in it is that of a
+                // trapezoid through its midpoint.
+                let middle = 0.5 * (x0 + x1) - x0_floor;
+                line[i0] += d * (1.0 - middle);
+                line[i0 + 1] += d * middle;
+            } else {
+                let slope = 1.0 / (x1 - x0);
+                let x0_fraction = x0 - x0_floor;
+                let first = 0.5 * slope * (1.0 - x0_fraction).powi(2);
+                let x1_fraction = x1 - x1_ceil + 1.0;
+                let last = 0.5 * slope * x1_fraction.powi(2);
+                line[i0] += d * first;
+                if i1 == i0 + 2 {
+                    line[i0 + 1] += d * (1.0 - first - last);
+                } else {
+                    let second = slope * (1.5 - x0_fraction);
+                    line[i0 + 1] += d * (second - first);
+                    for delta in &mut line[i0 + 2..i1 - 1] {
+                        *delta += d * slope;
+                    }
+                    let before_last = second + (i1 - i0 - 3) as f64 * slope;
+                    line[i1 - 1] += d * (1.0 - before_last - last);
+                }
+                line[i1] += d * last;
+            }
+            x = x_next;
+        }
+    }
+
+    /// Calls `f` with the position in the block and the coverage of every
+    /// pixel the polygon overlaps.
+    fn for_each(&self, mut f: impl FnMut(u32, u32, f64)) {
+        let stride = self.size[0] + 2;
+        for (y, row) in self.deltas.chunks(stride).enumerate() {
+            let mut area = 0.0;
+            for (x, delta) in row[..self.size[0]].iter().enumerate() {
+                area += delta;
+                // Rounding leaves crumbs of area outside the polygon.
+                let covered = f64::abs(area).min(1.0);
+                if covered > 1e-9 {
+                    f(x as u32, y as u32, covered);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the area of a simple polygon and the second moments of that area
+/// about its centroid, `[area, xx, yy, xy]`, each moment divided by the area.
+fn polygon_moments(points: &[[f64; 2]]) -> [f64; 4] {
+    let (mut area, mut cx, mut cy) = (0.0, 0.0, 0.0);
+    let (mut xx, mut yy, mut xy) = (0.0, 0.0, 0.0);
+    for i in 0..points.len() {
+        let [x0, y0] = points[i];
+        let [x1, y1] = points[(i + 1) % points.len()];
+        let cross = x0 * y1 - x1 * y0;
+        area += cross;
+        cx += (x0 + x1) * cross;
+        cy += (y0 + y1) * cross;
+        xx += (x0 * x0 + x0 * x1 + x1 * x1) * cross;
+        yy += (y0 * y0 + y0 * y1 + y1 * y1) * cross;
+        xy += (x0 * y1 + 2.0 * x0 * y0 + 2.0 * x1 * y1 + x1 * y0) * cross;
+    }
+    // The sums are signed by the winding direction, which cancels out below.
+    area /= 2.0;
+    if area == 0.0 {
+        return [0.0; 4];
+    }
+    let (cx, cy) = (cx / (6.0 * area), cy / (6.0 * area));
+    [
+        area.abs(),
+        xx / (12.0 * area) - cx * cx,
+        yy / (12.0 * area) - cy * cy,
+        xy / (24.0 * area) - cx * cy,
+    ]
+}
+
+fn is_convex(vertices: &[[f64; 2]]) -> bool {
+    let n = vertices.len();
+    let mut sign = 0.0;
+    for i in 0..n {
+        let a = vertices[i];
+        let b = vertices[(i + 1) % n];
+        let c = vertices[(i + 2) % n];
+        let cross = (b[0] - a[0]) * (c[1] - b[1]) - (b[1] - a[1]) * (c[0] - b[0]);
+        if cross == 0.0 {
+            return false;
+        }
+        if sign == 0.0 {
+            sign = cross.signum();
+        } else if cross.signum() != sign {
+            return false;
+        }
+    }
+    true
+}
+
+fn jitter_vertices(vertices: &mut [[f64; 2]], x_range: f64, y_range: f64, rng: &mut impl Rng) {
+    for vertex in vertices.iter_mut() {
+        if rng.gen::<f64>() < 0.5 {
+            vertex[0] += rng.gen_range(-x_range..=x_range);
+            vertex[1] += rng.gen_range(-y_range..=y_range);
+        }
+    }
+}
+
+fn jitter_lengths(lengths: &mut [f64], range: f64, rng: &mut impl Rng) {
+    for length in lengths.iter_mut() {
+        if rng.gen::<f64>() < 0.5 {
+            *length = (*length + rng.gen_range(-range..=range)).max(1.0);
+        }
+    }
+}
+
+fn jitter_angle(angle: &mut f64, range: f64, rng: &mut impl Rng) {
+    if rng.gen::<f64>() < 0.5 && range > 0.0 {
+        *angle = (*angle + rng.gen_range(-range..=range)).rem_euclid(180.0);
+    }
+}
+
+fn pick<T: Copy, const N: usize>(a: &[T; N], b: &[T; N], rng: &mut impl Rng) -> [T; N] {
+    let mut child = *a;
+    for (i, value) in child.iter_mut().enumerate() {
+        if rng.gen::<f64>() >= 0.5 {
+            *value = b[i];
+        }
+    }
+    child
+}
+
+fn pick_one<T>(a: T, b: T, rng: &mut impl Rng) -> T {
+    if rng.gen::<f64>() < 0.5 {
+        a
+    } else {
+        b
+    }
+}
+
+fn svg_polygon(vertices: &[[f64; 2]], precision: usize, fill: String, opacity: String) -> Polygon {
+    let number = |value: f64| format_number(value, precision);
+    let points = vertices
+        .iter()
+        .map(|v| format!("{},{}", number(v[0]), number(v[1])))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Polygon::new()
+        .set("points", points)
+        .set("fill", fill)
+        .set("fill-opacity", opacity)
+}
+
+fn svg_rotation(angle: f64, center: [f64; 2], precision: usize) -> String {
+    format!(
+        "rotate({} {} {})",
+        format_number(angle, precision),
+        format_number(center[0], precision),
+        format_number(center[1], precision)
+    )
+}
+
+/// Writes `value` with at most `precision` decimal places and no trailing zeros.
+fn format_number(value: f64, precision: usize) -> String {
+    let text = format!("{:.*}", precision, value);
+    let text = if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &text
+    };
+    match text {
+        "-0" => "0".to_string(),
+        text => text.to_string(),
+    }
+}
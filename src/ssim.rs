@@ -0,0 +1,502 @@
+use crate::algo::blend_pixel;
+use crate::shape::Shape;
+use image::RgbImage;
+
+/// Side length of the square windows SSIM statistics are gathered over.
+const WINDOW: u32 = 8;
+/// Distance between neighboring windows, so each pixel falls in up to four of them.
+const STRIDE: u32 = 4;
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+/// Exponents of successive scales, the first three of Wang, Simoncelli and
+/// Bovik's MS-SSIM, normalized to sum to one over the scales in use.
+const SCALE_WEIGHTS: [f64; 3] = [0.0448, 0.2856, 0.3001];
+
+/// Running sums of a window's canvas values, per channel.
+#[derive(Clone, Copy, Default)]
+struct WindowSums {
+    x: [f64; 3],
+    xx: [f64; 3],
+    xy: [f64; 3],
+}
+
+/// Windowed SSIM state for one level of the image pyramid.
+struct Scale {
+    shift: u32,
+    width: u32,
+    height: u32,
+    window: u32,
+    windows_x: u32,
+    windows_y: u32,
+    exponent: f64,
+    /// Only the coarsest scale compares mean brightness; the finer ones only
+    /// contrast and structure.
+    luminance: bool,
+    window_weights: Vec<f64>,
+    window_weight_total: f64,
+    canvas: Vec<[f64; 3]>,
+    reference: Vec<[f64; 3]>,
+    reference_sums: Vec<([f64; 3], [f64; 3])>,
+    sums: Vec<WindowSums>,
+    ssim: Vec<f64>,
+    total: f64,
+}
+
+/// The pixels of one scale a shape changes, as a dense block of deltas.
+struct Patch {
+    x0: u32,
+    y0: u32,
+    width: u32,
+    height: u32,
+    deltas: Vec<[f64; 3]>,
+}
+
+/// Structural dissimilarity, `1 - SSIM`, between the canvas and the reference.
+///
+/// SSIM depends on each window only through sums of canvas values, squares and
+/// products with the reference, so like the per-pixel metrics a candidate is
+/// scored by how it changes the windows under its bounding box. With more than
+/// one scale this is MS-SSIM over a box-filtered pyramid: the product of each
+/// scale's mean contrast and structure terms, and at the coarsest scale also the
+/// luminance term, raised to the MS-SSIM exponents.
+/// Each window counts in proportion to the mean pixel weight it covers.
+pub struct StructuralErrors {
+    width: u32,
+    height: u32,
+    scales: Vec<Scale>,
+}
+
+impl StructuralErrors {
+    pub fn new(
+        canvas_image: &RgbImage,
+        reference_image: &RgbImage,
+        weights: &[f64],
+        num_scales: u32,
+    ) -> Self {
+        let (width, height) = canvas_image.dimensions();
+        let num_scales = (0..num_scales)
+            .take_while(|&s| s == 0 || (width.min(height) >> s) >= WINDOW)
+            .count();
+        let weight_sum: f64 = SCALE_WEIGHTS[..num_scales].iter().sum();
+
+        let scales = (0..num_scales as u32)
+            .map(|shift| {
+                let scale_width = width >> shift;
+                let scale_height = height >> shift;
+                let window = WINDOW.min(scale_width).min(scale_height);
+                let windows_x = (scale_width - window) / STRIDE + 1;
+                let windows_y = (scale_height - window) / STRIDE + 1;
+
+                let mut scale = Scale {
+                    shift,
+                    width: scale_width,
+                    height: scale_height,
+                    window,
+                    windows_x,
+                    windows_y,
+                    exponent: SCALE_WEIGHTS[shift as usize] / weight_sum,
+                    luminance: shift as usize == num_scales - 1,
+                    window_weights: Vec::new(),
+                    window_weight_total: 0.0,
+                    canvas: downsample(canvas_image, shift),
+                    reference: downsample(reference_image, shift),
+                    reference_sums: Vec::new(),
+                    sums: Vec::new(),
+                    ssim: Vec::new(),
+                    total: 0.0,
+                };
+
+                let pixel_weights = downsample_weights(weights, width, shift);
+                for wy in 0..windows_y {
+                    for wx in 0..windows_x {
+                        let mut reference_sums = ([0.0; 3], [0.0; 3]);
+                        let mut sums = WindowSums::default();
+                        let mut window_weight = 0.0;
+                        for (x, y) in scale.window_pixels(wx, wy) {
+                            let i = (y * scale_width + x) as usize;
+                            window_weight += pixel_weights[i] / (window * window) as f64;
+                            let (c, r) = (scale.canvas[i], scale.reference[i]);
+                            for ch in 0..3 {
+                                reference_sums.0[ch] += r[ch];
+                                reference_sums.1[ch] += r[ch] * r[ch];
+                                sums.x[ch] += c[ch];
+                                sums.xx[ch] += c[ch] * c[ch];
+                                sums.xy[ch] += c[ch] * r[ch];
+                            }
+                        }
+                        scale.reference_sums.push(reference_sums);
+                        scale.sums.push(sums);
+                        scale.window_weights.push(window_weight);
+                    }
+                }
+                scale.window_weight_total = scale.window_weights.iter().sum();
+                if scale.window_weight_total == 0.0 {
+                    scale.window_weights.fill(1.0);
+                    scale.window_weight_total = scale.window_weights.len() as f64;
+                }
+                scale.ssim = (0..scale.sums.len())
+                    .map(|w| scale.window_ssim(w, &scale.sums[w]))
+                    .collect();
+                scale.total = scale
+                    .ssim
+                    .iter()
+                    .zip(&scale.window_weights)
+                    .map(|(ssim, weight)| ssim * weight)
+                    .sum();
+                scale
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            scales,
+        }
+    }
+
+    /// Dissimilarity the canvas would have with `shape` drawn on top.
+    pub fn error_with(&self, shape: &Shape, canvas_image: &RgbImage) -> f64 {
+        let color = shape.color();
+        let patches = self.patches(shape, |x, y, coverage| {
+            blend_pixel(canvas_image.get_pixel(x, y).0, color, coverage)
+        });
+        self.error_with_patches(&patches)
+    }
+
+    /// Dissimilarity the canvas would have with each of `pixels` set to its color.
+    pub fn error_with_pixels(&self, pixels: &[([u32; 2], [u8; 3])]) -> f64 {
+        self.error_with_patches(&self.pixel_patches(pixels))
+    }
+
+    fn error_with_patches(&self, patches: &[Option<Patch>]) -> f64 {
+        let mut similarity = 1.0;
+        for (scale, patch) in self.scales.iter().zip(patches) {
+            let mut total = scale.total;
+            if let Some(patch) = patch {
+                scale.for_each_changed_window(patch, |w, sums| {
+                    total += scale.window_weights[w] * (scale.window_ssim(w, &sums) - scale.ssim[w]);
+                });
+            }
+            // Anticorrelated scales would raise a negative mean to a fractional
+            // power, so they count as no similarity at all.
+            similarity *= (total / scale.window_weight_total).max(0.0).powf(scale.exponent);
+        }
+        1.0 - similarity
+    }
+
+    /// Refreshes the windows under `shape` after it has been drawn onto `canvas_image`.
+    pub fn update(&mut self, shape: &Shape, canvas_image: &RgbImage) {
+        let patches = self.patches(shape, |x, y, _| canvas_image.get_pixel(x, y).0);
+        self.apply_patches(&patches);
+    }
+
+    /// Refreshes the windows under `pixels` after each has been set to its color.
+    pub fn update_pixels(&mut self, pixels: &[([u32; 2], [u8; 3])]) {
+        let patches = self.pixel_patches(pixels);
+        self.apply_patches(&patches);
+    }
+
+    fn apply_patches(&mut self, patches: &[Option<Patch>]) {
+        for (scale, patch) in self.scales.iter_mut().zip(patches) {
+            let Some(patch) = patch else {
+                continue;
+            };
+
+            let mut changed = Vec::new();
+            scale.for_each_changed_window(patch, |w, sums| changed.push((w, sums)));
+            for (w, sums) in changed {
+                let ssim = scale.window_ssim(w, &sums);
+                scale.total += scale.window_weights[w] * (ssim - scale.ssim[w]);
+                scale.ssim[w] = ssim;
+                scale.sums[w] = sums;
+            }
+            for py in 0..patch.height {
+                for px in 0..patch.width {
+                    let i = ((patch.y0 + py) * scale.width + patch.x0 + px) as usize;
+                    let delta = patch.deltas[(py * patch.width + px) as usize];
+                    for (value, delta) in scale.canvas[i].iter_mut().zip(delta) {
+                        *value += delta;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Collects, per scale, how each pixel under `shape` would change if it took
+    /// the value `new_pixel` returns for it and its coverage at full resolution.
+    fn patches(
+        &self,
+        shape: &Shape,
+        new_pixel: impl Fn(u32, u32, f64) -> [u8; 3],
+    ) -> Vec<Option<Patch>> {
+        let mut patches = self.empty_patches(shape.bounding_box());
+        shape.rasterize((self.width, self.height), |x, y, coverage| {
+            self.add_delta(&mut patches, x, y, new_pixel(x, y, coverage));
+        });
+        patches
+    }
+
+    /// Collects, per scale, how the canvas would change with each of `pixels`
+    /// set to its color.
+    fn pixel_patches(&self, pixels: &[([u32; 2], [u8; 3])]) -> Vec<Option<Patch>> {
+        let mut min = [f64::MAX; 2];
+        let mut max = [f64::MIN; 2];
+        for (p, _) in pixels {
+            for i in 0..2 {
+                min[i] = min[i].min(p[i] as f64);
+                max[i] = max[i].max(p[i] as f64);
+            }
+        }
+        let mut patches = self.empty_patches((min, max));
+        for &([x, y], new) in pixels {
+            self.add_delta(&mut patches, x, y, new);
+        }
+        patches
+    }
+
+    /// Creates zeroed patches covering the full-resolution box `(min, max)`.
+    fn empty_patches(&self, (min, max): ([f64; 2], [f64; 2])) -> Vec<Option<Patch>> {
+        if max[0] < 0.0 || max[1] < 0.0 || min[0] >= self.width as f64 || min[1] >= self.height as f64 {
+            return self.scales.iter().map(|_| None).collect();
+        }
+        let x0 = min[0].floor().max(0.0) as u32;
+        let y0 = min[1].floor().max(0.0) as u32;
+        let x1 = (max[0].ceil() as u32).min(self.width - 1);
+        let y1 = (max[1].ceil() as u32).min(self.height - 1);
+
+        self.scales
+            .iter()
+            .map(|scale| {
+                let (px0, py0) = (x0 >> scale.shift, y0 >> scale.shift);
+                if px0 >= scale.width || py0 >= scale.height {
+                    return None;
+                }
+                let width = (x1 >> scale.shift).min(scale.width - 1) - px0 + 1;
+                let height = (y1 >> scale.shift).min(scale.height - 1) - py0 + 1;
+                Some(Patch {
+                    x0: px0,
+                    y0: py0,
+                    width,
+                    height,
+                    deltas: vec![[0.0; 3]; (width * height) as usize],
+                })
+            })
+            .collect()
+    }
+
+    /// Records in every scale that the full-resolution pixel `(x, y)` becomes `new`.
+    fn add_delta(&self, patches: &mut [Option<Patch>], x: u32, y: u32, new: [u8; 3]) {
+        let full = &self.scales[0];
+        let old = full.canvas[(y * full.width + x) as usize];
+        for (scale, patch) in self.scales.iter().zip(patches.iter_mut()) {
+            let Some(patch) = patch else {
+                continue;
+            };
+            let (sx, sy) = (x >> scale.shift, y >> scale.shift);
+            if sx >= scale.width || sy >= scale.height {
+                continue;
+            }
+            let area = (1u32 << (2 * scale.shift)) as f64;
+            let delta = &mut patch.deltas[((sy - patch.y0) * patch.width + sx - patch.x0) as usize];
+            for ch in 0..3 {
+                delta[ch] += (new[ch] as f64 - old[ch]) / area;
+            }
+        }
+    }
+}
+
+impl Scale {
+    fn window_pixels(&self, wx: u32, wy: u32) -> impl Iterator<Item = (u32, u32)> {
+        let (x0, y0, size) = (wx * STRIDE, wy * STRIDE, self.window);
+        (y0..y0 + size).flat_map(move |y| (x0..x0 + size).map(move |x| (x, y)))
+    }
+
+    /// Range of window indices along one axis whose span includes `lo..=hi`.
+    fn window_range(&self, lo: u32, hi: u32, count: u32) -> Option<(u32, u32)> {
+        let first = (lo + STRIDE).saturating_sub(self.window) / STRIDE;
+        let last = (hi / STRIDE).min(count - 1);
+        (first <= last).then_some((first, last))
+    }
+
+    /// Calls `f` with the index and updated sums of every window `patch` touches.
+    fn for_each_changed_window(&self, patch: &Patch, mut f: impl FnMut(usize, WindowSums)) {
+        let Some((wx0, wx1)) =
+            self.window_range(patch.x0, patch.x0 + patch.width - 1, self.windows_x)
+        else {
+            return;
+        };
+        let Some((wy0, wy1)) =
+            self.window_range(patch.y0, patch.y0 + patch.height - 1, self.windows_y)
+        else {
+            return;
+        };
+
+        for wy in wy0..=wy1 {
+            for wx in wx0..=wx1 {
+                let w = (wy * self.windows_x + wx) as usize;
+                let mut sums = self.sums[w];
+                let mut touched = false;
+                for (x, y) in self.window_pixels(wx, wy) {
+                    if x < patch.x0
+                        || y < patch.y0
+                        || x >= patch.x0 + patch.width
+                        || y >= patch.y0 + patch.height
+                    {
+                        continue;
+                    }
+                    let delta = patch.deltas[((y - patch.y0) * patch.width + x - patch.x0) as usize];
+                    if delta == [0.0; 3] {
+                        continue;
+                    }
+                    let i = (y * self.width + x) as usize;
+                    let (c, r) = (self.canvas[i], self.reference[i]);
+                    for ch in 0..3 {
+                        sums.x[ch] += delta[ch];
+                        sums.xx[ch] += delta[ch] * (2.0 * c[ch] + delta[ch]);
+                        sums.xy[ch] += delta[ch] * r[ch];
+                    }
+                    touched = true;
+                }
+                if touched {
+                    f(w, sums);
+                }
+            }
+        }
+    }
+
+    /// SSIM of window `w` with the canvas sums `sums`, without the luminance
+    /// term unless this is the coarsest scale.
+    fn window_ssim(&self, w: usize, sums: &WindowSums) -> f64 {
+        let n = (self.window * self.window) as f64;
+        let (sum_y, sum_yy) = self.reference_sums[w];
+        let mut ssim = 0.0;
+        for ch in 0..3 {
+            let mu_x = sums.x[ch] / n;
+            let mu_y = sum_y[ch] / n;
+            let var_x = sums.xx[ch] / n - mu_x * mu_x;
+            let var_y = sum_yy[ch] / n - mu_y * mu_y;
+            let cov = sums.xy[ch] / n - mu_x * mu_y;
+            let contrast_structure = (2.0 * cov + C2) / (var_x + var_y + C2);
+            ssim += if self.luminance {
+                (2.0 * mu_x * mu_y + C1) / (mu_x * mu_x + mu_y * mu_y + C1) * contrast_structure
+            } else {
+                contrast_structure
+            };
+        }
+        ssim / 3.0
+    }
+}
+
+/// Box-filters `image` down by a factor of `2^shift` in each direction.
+pub(crate) fn downsample(image: &RgbImage, shift: u32) -> Vec<[f64; 3]> {
+    let (width, height) = (image.width() >> shift, image.height() >> shift);
+    let factor = 1u32 << shift;
+    let area = (factor * factor) as f64;
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0; 3];
+            for dy in 0..factor {
+                for dx in 0..factor {
+                    let p = image.get_pixel(x * factor + dx, y * factor + dy).0;
+                    for ch in 0..3 {
+                        sum[ch] += p[ch] as f64;
+                    }
+                }
+            }
+            pixels.push(sum.map(|s| s / area));
+        }
+    }
+    pixels
+}
+
+/// Box-filters row-major per-pixel `weights` for an image `width` pixels wide
+/// down by a factor of `2^shift` in each direction.
+pub(crate) fn downsample_weights(weights: &[f64], width: u32, shift: u32) -> Vec<f64> {
+    let height = weights.len() as u32 / width;
+    let factor = 1u32 << shift;
+    let area = (factor * factor) as f64;
+
+    let mut downsampled = Vec::new();
+    for y in 0..height >> shift {
+        for x in 0..width >> shift {
+            let mut sum = 0.0;
+            for dy in 0..factor {
+                for dx in 0..factor {
+                    sum += weights[((y * factor + dy) * width + x * factor + dx) as usize];
+                }
+            }
+            downsampled.push(sum / area);
+        }
+    }
+    downsampled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    /// Mean over the windows and channels of one scale of the SSIM contrast and
+    /// structure term, times the luminance term if `luminance` is set, computed
+    /// straight from the pixels of each window.
+    fn mean_term(canvas: &[[f64; 3]], reference: &[[f64; 3]], size: u32, luminance: bool) -> f64 {
+        let window = WINDOW.min(size);
+        let windows = (size - window) / STRIDE + 1;
+        let n = (window * window) as f64;
+        let mut total = 0.0;
+        for wy in 0..windows {
+            for wx in 0..windows {
+                for ch in 0..3 {
+                    let pixels: Vec<(f64, f64)> = (0..window * window)
+                        .map(|i| {
+                            let (x, y) = (wx * STRIDE + i % window, wy * STRIDE + i / window);
+                            let i = (y * size + x) as usize;
+                            (canvas[i][ch], reference[i][ch])
+                        })
+                        .collect();
+                    let mu_x = pixels.iter().map(|p| p.0).sum::<f64>() / n;
+                    let mu_y = pixels.iter().map(|p| p.1).sum::<f64>() / n;
+                    let var_x = pixels.iter().map(|p| (p.0 - mu_x).powi(2)).sum::<f64>() / n;
+                    let var_y = pixels.iter().map(|p| (p.1 - mu_y).powi(2)).sum::<f64>() / n;
+                    let cov = pixels.iter().map(|p| (p.0 - mu_x) * (p.1 - mu_y)).sum::<f64>() / n;
+
+                    let mut term = (2.0 * cov + C2) / (var_x + var_y + C2);
+                    if luminance {
+                        term *= (2.0 * mu_x * mu_y + C1) / (mu_x * mu_x + mu_y * mu_y + C1);
+                    }
+                    total += term;
+                }
+            }
+        }
+        total / (windows * windows * 3) as f64
+    }
+
+    #[test]
+    fn ms_ssim_is_a_product_of_scale_terms() {
+        let canvas = RgbImage::from_fn(32, 32, |x, y| {
+            Rgb([(x * 7 + y * 3) as u8, (x * y % 200) as u8, ((x ^ y) * 8) as u8])
+        });
+        let reference = RgbImage::from_fn(32, 32, |x, y| {
+            Rgb([(y * 8) as u8, ((x + y) * 4) as u8, (120 + x * 3 % 50) as u8])
+        });
+        let errors = StructuralErrors::new(&canvas, &reference, &[1.0; 32 * 32], 3);
+
+        let exponent_sum: f64 = SCALE_WEIGHTS.iter().sum();
+        let mut ms_ssim = 1.0;
+        for shift in 0..3 {
+            let term = mean_term(
+                &downsample(&canvas, shift),
+                &downsample(&reference, shift),
+                32 >> shift,
+                shift == 2,
+            );
+            assert!(term > 0.0);
+            ms_ssim *= term.powf(SCALE_WEIGHTS[shift as usize] / exponent_sum);
+        }
+
+        let error = errors.error_with_pixels(&[]);
+        assert!((error - (1.0 - ms_ssim)).abs() < 1e-9, "{error} != {}", 1.0 - ms_ssim);
+    }
+}
@@ -0,0 +1,25 @@
+//! Runs with several islands, which exchange their best shapes as they go.
+
+use image::{Rgb, RgbImage};
+use triklops::optimizer::OptimizerKind;
+use triklops::{render, AlgorithmParams, ShapeKind};
+
+#[test]
+fn mixed_shapes_migrate_between_cma_es_islands() {
+    let reference_image = RgbImage::from_fn(32, 32, |x, y| Rgb([(x * 8) as u8, (y * 8) as u8, 90]));
+    for seed in 0..4 {
+        let params = AlgorithmParams {
+            image_size: 32,
+            num_triangles: 4,
+            num_generations: 8,
+            population_size: 6,
+            shape_kind: ShapeKind::Mixed,
+            optimizer: OptimizerKind::CmaEs,
+            islands: 3,
+            migration_interval: 1,
+            seed: Some(seed),
+            ..Default::default()
+        };
+        render(params, &reference_image, None);
+    }
+}
@@ -0,0 +1,132 @@
+//! Checks that the canvas shapes are scored against looks like the exported SVG
+//! does in a renderer, anti-aliased edges included.
+
+use image::{Rgb, RgbImage};
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{Options, Tree};
+use triklops::{draw_shape_onto_canvas, new_document, AlgorithmParams};
+use triklops::{Circle, Ellipse, Quad, Rect, Shape, Triangle};
+
+const SIZE: u32 = 64;
+
+/// Renderers quantize the coverage of edge pixels, tiny-skia to sixteenths, so
+/// the SVG is rendered this many times larger and scaled down to get close to
+/// the exact coverage.
+const SUPERSAMPLING: u32 = 8;
+
+fn render_canvas(shapes: &[Shape]) -> RgbImage {
+    let mut canvas = RgbImage::new(SIZE, SIZE);
+    for shape in shapes {
+        draw_shape_onto_canvas(&mut canvas, shape);
+    }
+    canvas
+}
+
+fn render_svg(shapes: &[Shape]) -> RgbImage {
+    let params = AlgorithmParams {
+        image_size: SIZE,
+        ..Default::default()
+    };
+    let document = shapes
+        .iter()
+        .fold(new_document(&params, None), |document, shape| {
+            document.add(shape.svg_node(6))
+        });
+    let tree = Tree::from_str(&document.to_string(), &Options::default()).unwrap();
+    let scale = SUPERSAMPLING as f32;
+    let mut pixmap = Pixmap::new(SIZE * SUPERSAMPLING, SIZE * SUPERSAMPLING).unwrap();
+    resvg::render(
+        &tree,
+        Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // The black background makes every pixel opaque, so premultiplied colors
+    // are the plain ones.
+    RgbImage::from_fn(SIZE, SIZE, |x, y| {
+        let mut sum = [0u32; 3];
+        for dy in 0..SUPERSAMPLING {
+            for dx in 0..SUPERSAMPLING {
+                let pixel = pixmap
+                    .pixel(x * SUPERSAMPLING + dx, y * SUPERSAMPLING + dy)
+                    .unwrap();
+                sum[0] += pixel.red() as u32;
+                sum[1] += pixel.green() as u32;
+                sum[2] += pixel.blue() as u32;
+            }
+        }
+        let count = SUPERSAMPLING * SUPERSAMPLING;
+        Rgb(sum.map(|total| ((total + count / 2) / count) as u8))
+    })
+}
+
+/// Asserts that no channel of any pixel differs much between the canvas and the
+/// SVG, and that they agree closely on average.
+fn assert_matches_svg(shapes: &[Shape]) {
+    let canvas = render_canvas(shapes);
+    let svg = render_svg(shapes);
+    let differences: Vec<u8> = canvas
+        .as_raw()
+        .iter()
+        .zip(svg.as_raw())
+        .map(|(a, b)| a.abs_diff(*b))
+        .collect();
+    let max = *differences.iter().max().unwrap();
+    let mean = differences.iter().map(|&d| d as f64).sum::<f64>() / differences.len() as f64;
+    // Where the edges of translucent shapes cross, blending each shape's edge
+    // coverage in turn, as renderers do, differs a little from supersampling.
+    assert!(max <= 12, "largest difference {max}");
+    assert!(mean < 0.1, "mean difference {mean}");
+}
+
+#[test]
+fn polygons_match_svg() {
+    assert_matches_svg(&[
+        Shape::Triangle(Triangle {
+            vertices: [[3.2, 5.7], [58.9, 12.4], [20.5, 60.1]],
+            color: [250, 200, 40, 255],
+        }),
+        Shape::Quad(Quad {
+            vertices: [[30.3, 8.8], [61.5, 30.2], [44.1, 59.6], [14.7, 33.3]],
+            color: [40, 120, 230, 160],
+        }),
+        Shape::Rect(Rect {
+            center: [22.4, 40.6],
+            size: [30.2, 11.7],
+            angle: 33.0,
+            color: [200, 30, 90, 200],
+        }),
+    ]);
+}
+
+#[test]
+fn curves_match_svg() {
+    assert_matches_svg(&[
+        Shape::Ellipse(Ellipse {
+            center: [31.6, 28.3],
+            radii: [26.4, 12.9],
+            angle: 117.0,
+            color: [90, 220, 120, 255],
+        }),
+        Shape::Circle(Circle {
+            center: [44.5, 45.25],
+            radius: 15.3,
+            color: [240, 240, 240, 128],
+        }),
+    ]);
+}
+
+#[test]
+fn slivers_match_svg() {
+    // Thinner than a pixel, so sampling pixel centers would miss most of it.
+    assert_matches_svg(&[
+        Shape::Triangle(Triangle {
+            vertices: [[2.0, 3.0], [61.0, 50.0], [61.4, 50.6]],
+            color: [255, 255, 255, 255],
+        }),
+        Shape::Triangle(Triangle {
+            vertices: [[10.25, 60.0], [10.75, 60.0], [10.5, 2.0]],
+            color: [255, 64, 0, 255],
+        }),
+    ]);
+}